@@ -1,8 +1,8 @@
 #!/usr/local/bin/bash
 #set -x
 
-declare -a DIRS=("bin" "dev" "mnt" "proc" "tmp" "etc/defaults")
-declare -a EXECS=("COPYRIGHT" "/libexec/ld-elf.so.1" "bin/sh" "/sbin/ifconfig" "/sbin/route" "usr/sbin/jail")
+declare -a DIRS=("bin" "dev" "mnt" "proc" "tmp" "etc/defaults" "var/db" "var/run")
+declare -a EXECS=("COPYRIGHT" "/libexec/ld-elf.so.1" "bin/sh" "/sbin/ifconfig" "/sbin/route" "/sbin/rtsol" "/sbin/dhclient" "/sbin/dhclient-script" "usr/sbin/jail")
 
 #### End user editable vars
 
@@ -5,9 +5,16 @@ use std::collections::BTreeMap as Map;
 
 
 use toml;
+use hooks;
+use errors::{ValidationError, ValidationErrors};
+use jail_config::{JailParams, JailParam, Threshold, mac_oui_error, thresholds_errors};
+use uuid::Uuid;
 extern crate slog;
 
-static CONFIG: &'static str = "/etc/vmadm.toml";
+pub static CONFIG: &'static str = "/etc/vmadm.toml";
+
+/// devfs rulesets below this are left to the system and the admin
+pub const DEVFS_RESERVED: u32 = 100;
 
 /// Global settings
 #[derive(Debug, Deserialize, Serialize)]
@@ -22,6 +29,28 @@ pub struct Settings {
     pub image_dir: String,
     #[serde(default = "devfs_ruleset")]
     pub devfs_ruleset: u32,
+    /// First devfs ruleset number used for the rulesets generated per
+    /// jail
+    #[serde(default = "default_devfs_ruleset_base")]
+    pub devfs_ruleset_base: u32,
+    /// Prefix for generated mac addresses
+    #[serde(default = "default_mac_oui")]
+    pub mac_oui: String,
+    /// Reject unknown properties instead of warning about them
+    #[serde(default = "default_strict")]
+    pub strict: bool,
+    /// Number of config revisions kept per jail
+    #[serde(default = "default_history_size")]
+    pub history_size: usize,
+    /// Default rctl thresholds per resource
+    #[serde(default = "default_thresholds")]
+    pub thresholds: Map<String, Vec<Threshold>>,
+    /// Default jail(8) parameters for the outer and inner jail
+    #[serde(default = "default_jail_params")]
+    pub jail_params: JailParams,
+    /// Hooks run for every jail, per event
+    #[serde(default = "default_hooks")]
+    pub hooks: Map<String, Vec<String>>,
     pub networks: Map<String, String>,
 }
 
@@ -29,10 +58,40 @@ fn devfs_ruleset() -> u32 {
     4
 }
 
+fn default_devfs_ruleset_base() -> u32 {
+    1000
+}
+
+fn default_mac_oui() -> String {
+    "02".to_string()
+}
+
+fn default_strict() -> bool {
+    false
+}
+
+fn default_history_size() -> usize {
+    10
+}
+
+fn default_thresholds() -> Map<String, Vec<Threshold>> {
+    Map::new()
+}
+
+fn default_jail_params() -> JailParams {
+    JailParams::new()
+}
+
+fn default_hooks() -> Map<String, Vec<String>> {
+    Map::new()
+}
+
 /// Config object
 #[derive(Debug)]
 pub struct Config {
     pub settings: Settings,
+    /// Id of the current request
+    pub req_id: String,
 }
 
 fn default_conf_dir() -> String {
@@ -47,6 +106,41 @@ fn default_repo() -> String {
     "https://bsd.project-fifo.net/images".to_string()
 }
 
+impl Settings {
+    /// Checks the settings for errors
+    pub fn errors(&self) -> Vec<ValidationError> {
+        let mut errors = hooks::errors("hooks", &self.hooks);
+        // generated rulesets are deleted on stop so they must not
+        // overlap with the ones of the system
+        if self.devfs_ruleset_base <= self.devfs_ruleset ||
+            self.devfs_ruleset_base < DEVFS_RESERVED || self.devfs_ruleset_base > 65535
+        {
+            errors.push(ValidationError::new(
+                "devfs_ruleset_base",
+                format!(
+                    "Must be above devfs_ruleset and between {} and 65535",
+                    DEVFS_RESERVED
+                ).as_str(),
+            ))
+        }
+        if let Some(error) = mac_oui_error(self.mac_oui.as_str()) {
+            errors.push(ValidationError::new("mac_oui", error))
+        }
+        errors.append(&mut thresholds_errors("thresholds", &self.thresholds, None));
+        errors.append(&mut self.jail_params.errors("jail_params"));
+        match self.jail_params.outer.get("children.max") {
+            Some(&JailParam::Int(n)) if n < 1 => {
+                errors.push(ValidationError::new(
+                    "jail_params.outer.children.max",
+                    "The outer jail needs children.max of at least 1",
+                ))
+            }
+            _ => (),
+        }
+        errors
+    }
+}
+
 impl Config {
     /// Initializes config
     pub fn new() -> Result<Self, Box<Error>> {
@@ -57,7 +151,14 @@ impl Config {
             "Failed to read config file.",
         );
         let settings: Settings = toml::from_str(contents.as_str())?;
-        Ok(Config { settings: settings })
+        let errors = settings.errors();
+        if !errors.is_empty() {
+            return Err(ValidationErrors::bx(errors));
+        }
+        Ok(Config {
+            settings: settings,
+            req_id: Uuid::new_v4().hyphenated().to_string(),
+        })
     }
     // add code here
 }
@@ -19,6 +19,10 @@ impl ValidationErrors {
    pub fn bx(errors: Vec<ValidationError>) -> Box<Error> {
        Box::new(ValidationErrors::new(errors))
    }
+   /// The individual errors
+   pub fn errors(&self) -> &Vec<ValidationError> {
+       &self.errors
+   }
 }
 
 impl fmt::Display for ValidationErrors {
@@ -38,7 +42,7 @@ impl Error for ValidationErrors {
 
 
 /// Validation error for input validation
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct ValidationError {
     field: String,
     error: String,
@@ -52,6 +56,10 @@ impl ValidationError {
             error: String::from(error),
         }
     }
+    /// The path of the field the error is about
+    pub fn field(&self) -> &str {
+        self.field.as_str()
+    }
     // /// Create a new error in a box
     // pub fn bx(field: &str, error: &str) -> Box<Error> {
     //     Box::new(ValidationError::new(field, error))
@@ -0,0 +1,138 @@
+//! Hooks run around jail lifecycle events
+
+use std::collections::BTreeMap as Map;
+use std::error::Error;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use config::Config;
+use errors::{GenericError, ValidationError};
+use jail_config::JailConfig;
+use serde_json;
+
+/// Events hooks can be registered for
+pub static EVENTS: &'static [&'static str] = &[
+    "pre-create",
+    "post-create",
+    "pre-start",
+    "post-start",
+    "pre-stop",
+    "post-stop",
+    "pre-delete",
+    "post-delete",
+];
+
+/// Runs the hooks for an event, first the ones from the vmadm.toml
+/// then the ones of the jail. Each hook is called with the event and
+/// the uuid of the jail as arguments and gets the jail config on stdin.
+/// A failing `pre-` hook aborts the event, failing `post-` hooks are
+/// only logged.
+pub fn run(config: &Config, event: &str, jail: &JailConfig) -> Result<i32, Box<Error>> {
+    let uuid = jail.uuid.hyphenated().to_string();
+    let global = config.settings.hooks.get(event).into_iter().flat_map(
+        |hooks| hooks.iter(),
+    );
+    let local = jail.hooks.get(event).into_iter().flat_map(
+        |hooks| hooks.iter(),
+    );
+    for hook in global.chain(local) {
+        match run_hook(hook.as_str(), event, uuid.as_str(), jail) {
+            Ok(_) => (),
+            Err(e) => {
+                if event.starts_with("pre-") {
+                    crit!("hook failed"; "vm" => uuid.clone(), "event" => event, "hook" => hook.clone());
+                    return Err(GenericError::bx(
+                        format!("{} hook {} failed: {}", event, hook, e).as_str(),
+                    ));
+                }
+                warn!("hook failed"; "vm" => uuid.clone(), "event" => event, "hook" => hook.clone());
+            }
+        }
+    }
+    Ok(0)
+}
+
+fn run_hook(hook: &str, event: &str, uuid: &str, jail: &JailConfig) -> Result<i32, Box<Error>> {
+    debug!("running hook"; "vm" => uuid, "event" => event, "hook" => hook);
+    let mut child = Command::new(hook)
+        .args(&[event, uuid])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // hooks that don't read their input close stdin early
+        let _ = stdin.write_all(serde_json::to_string(jail)?.as_bytes());
+    }
+    let status = child.wait()?;
+    if status.success() {
+        Ok(0)
+    } else {
+        Err(GenericError::bx(format!("exited with {}", status).as_str()))
+    }
+}
+
+/// Checks the events and commands of hooks
+pub fn errors(path: &str, hooks: &Map<String, Vec<String>>) -> Vec<ValidationError> {
+    let mut errors = Vec::new();
+    for (event, commands) in hooks.iter() {
+        let field = format!("{}.{}", path, event);
+        if !EVENTS.contains(&event.as_str()) {
+            errors.push(ValidationError::new(field.as_str(), "Unknown event"));
+            continue;
+        }
+        for (i, command) in commands.iter().enumerate() {
+            if !command.starts_with('/') {
+                errors.push(ValidationError::new(
+                    format!("{}.{}", field, i).as_str(),
+                    "Hooks need an absolute path",
+                ))
+            }
+        }
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use hooks::*;
+    use config::Settings;
+    use toml;
+
+    fn config(hooks: &str) -> Config {
+        let settings: Settings = toml::from_str(
+            format!("pool = \"zroot/jails\"\n[networks]\n[hooks]\n{}", hooks).as_str(),
+        ).unwrap();
+        Config {
+            settings: settings,
+            req_id: String::from("test"),
+        }
+    }
+
+    #[test]
+    fn run_hooks() {
+        let config = config("pre-start = [\"/usr/bin/true\"]\npost-start = [\"/usr/bin/false\"]");
+        let json = r#"{"image_uuid": "616d4ab2-832c-11e7-9392-784f438c8d54",
+            "alias": "test", "hostname": "test", "max_physical_memory": 1024,
+            "cpu_cap": 100, "quota": 5}"#;
+        let mut jail = JailConfig::from_reader(&config, json.as_bytes()).unwrap();
+        assert!(run(&config, "pre-start", &jail).is_ok());
+        assert!(run(&config, "post-start", &jail).is_ok());
+        jail.hooks.insert(String::from("pre-start"), vec![String::from("/usr/bin/false")]);
+        assert!(run(&config, "pre-start", &jail).is_err());
+    }
+
+    #[test]
+    fn errors() {
+        let mut hooks = Map::new();
+        hooks.insert(
+            String::from("pre-start"),
+            vec![String::from("/usr/local/bin/dns"), String::from("dns")],
+        );
+        hooks.insert(String::from("pre-boot"), vec![]);
+        let fields: Vec<String> = super::errors("hooks", &hooks)
+            .iter()
+            .map(|e| String::from(e.field()))
+            .collect();
+        assert_eq!(vec!["hooks.pre-boot", "hooks.pre-start.1"], fields);
+    }
+}
@@ -3,13 +3,15 @@
 use std::error::Error;
 use std::fs::File;
 use std::io::Read;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::collections::BTreeMap as Map;
+use std::fmt;
 #[cfg(target_os = "freebsd")]
 use std::process::Command;
-#[cfg(target_os = "freebsd")]
-use errors::GenericError;
 
-use errors::{ValidationError, ValidationErrors};
+use errors::{GenericError, ValidationError, ValidationErrors};
 use config::Config;
+use hooks;
 
 use serde_json;
 use uuid::Uuid;
@@ -22,20 +24,36 @@ use rand::{thread_rng, Rng};
 pub struct NIC {
     /// Interface name
     pub interface: String,
-    /// Mac address for the nic
-    #[serde(default = "new_mac")]
+    /// Mac address for the nic, if empty one is generated when the
+    /// jail is created or the nic is added
+    #[serde(default)]
     pub mac: String,
     /// VLAN id for the nic
     #[serde(skip_serializing_if = "Option::is_none")]
     pub vlan: Option<u16>,
     /// The nic_tag for the nic to uise
     pub nic_tag: String,
-    /// The IP for the nic
+    /// The IP for the nic, or `dhcp` to have it configured by DHCP
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub ip: String,
     /// The netmask for the nic
-    pub netmask: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub netmask: Option<String>,
     /// The gateway for the nic
-    pub gateway: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gateway: Option<String>,
+    /// The IPv6 address for the nic in CIDR notation or `addrconf`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip6: Option<String>,
+    /// The IPv6 gateway for the nic
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gateway6: Option<String>,
+    /// All addresses of the nic in CIDR notation, `dhcp` or `addrconf`
+    #[serde(default = "empty_strings", skip_serializing_if = "Vec::is_empty")]
+    pub ips: Vec<String>,
+    /// All gateways of the nic
+    #[serde(default = "empty_strings", skip_serializing_if = "Vec::is_empty")]
+    pub gateways: Vec<String>,
     #[serde(default = "dflt_false")]
     /// If this nic is the primary interface or not
     pub primary: bool,
@@ -53,9 +71,13 @@ impl PartialEq for NIC {
             self.mac == other.mac &&
             self.vlan == other.vlan &&
             self.nic_tag == other.nic_tag &&
-            self.ip == self.ip &&
+            self.ip == other.ip &&
             self.netmask == other.netmask &&
             self.gateway == other.gateway &&
+            self.ip6 == other.ip6 &&
+            self.gateway6 == other.gateway6 &&
+            self.ips == other.ips &&
+            self.gateways == other.gateways &&
             self.primary == other.primary &&
             self.mtu == other.mtu &&
             self.network_uuid == other.network_uuid
@@ -107,34 +129,18 @@ impl NIC {
             None => return Err(GenericError::bx("bridge not configured")),
         }
 
-        let mut script = if self.vlan.is_some() {
-            // This may seem stupid but freebsd can't create a vlan interface
-            // that is not named vlan<X> or <interface>.<X>
-            // however once created it happiely renames it ...
-            format!(
-                "/sbin/ifconfig {epair}b.{vlan} create vlan {vlan} vlandev {epair}p; \
-                /sbin/ifconfig {epair}b.{vlan} name {iface}; \
-                /sbin/ifconfig {iface} inet {ip} {mask}; ",
-                epair = epair,
-                ip = self.ip,
-                mask = self.netmask,
-                iface = self.interface,
-                vlan = self.vlan.unwrap()
-            )
-        } else {
-            format!(
-                "/sbin/ifconfig {epair}b name {iface}; \
-                /sbin/ifconfig {iface} inet {ip} {mask}; ",
-                epair = epair,
-                ip = self.ip,
-                mask = self.netmask,
-                iface = self.interface
-            )
-        };
-        if self.primary {
-            let route = format!("/sbin/route add default -gateway {}; ", self.gateway);
-            script.push_str(route.as_str())
+        if let Some(mtu) = self.mtu {
+            // the host side of the epair has to match the MTU
+            let output = Command::new(IFCONFIG)
+                .args(&[epaira, "mtu", mtu.to_string().as_str()])
+                .output()
+                .expect("failed ifconfig");
+            if !output.status.success() {
+                return Err(GenericError::bx("could not set mtu"));
+            }
         }
+
+        let script = self.start_script(epair.as_str());
         let mut desc = String::from("VNic from jail ");
         desc.push_str(uuid.hyphenated().to_string().as_str());
         let output = Command::new(IFCONFIG)
@@ -154,31 +160,7 @@ impl NIC {
     #[cfg(not(target_os = "freebsd"))]
     pub fn get_iface(&self, _config: &Config, _uuid: &Uuid) -> Result<IFace, Box<Error>> {
         let epair = "epair0";
-        let script = if self.vlan.is_some() {
-            // This may seem stupid but freebsd can't create a vlan interface
-            // that is not named vlan<X> or <interface>.<X>
-            // however once created it happiely renames it ...
-            format!(
-                "/sbin/ifconfig {epair}b name {iface}p; \
-                /sbin/ifconfig {iface}p.{vlan} create vlan {vlan} vlandev {iface}p; \
-                /sbin/ifconfig {iface}p.{vlan} name {iface}; \
-                /sbin/ifconfig {iface} inet {ip} {mask}; ",
-                epair = epair,
-                ip = self.ip,
-                mask = self.netmask,
-                iface = self.interface,
-                vlan = self.vlan.unwrap()
-            )
-        } else {
-            format!(
-                "/sbin/ifconfig {epair}b name {iface}; \
-                /sbin/ifconfig {iface} inet {ip} {mask}; ",
-                epair = epair,
-                ip = self.ip,
-                mask = self.netmask,
-                iface = self.interface
-            )
-        };
+        let script = self.start_script(epair);
 
         Ok(IFace {
             iface: self.interface.clone(),
@@ -186,6 +168,343 @@ impl NIC {
             start_script: script,
         })
     }
+
+    /// Commands to set up the jail side of the epair, this renames it
+    /// (or the vlan interface on top of it) to the interface name, sets
+    /// mac address and MTU and configures the addresses.
+    pub fn start_script(&self, epair: &str) -> String {
+        let mtu = match self.mtu {
+            Some(mtu) => format!(" mtu {}", mtu),
+            None => String::new(),
+        };
+        let mut script = match self.vlan {
+            Some(vlan) => {
+                let mut script = format!(
+                    "/sbin/ifconfig {epair}b name {iface}p; ",
+                    epair = epair,
+                    iface = self.interface
+                );
+                // the vlan can't have a larger MTU then the parent
+                if self.mtu.is_some() {
+                    script.push_str(
+                        format!("/sbin/ifconfig {iface}p{mtu}; ", iface = self.interface, mtu = mtu)
+                            .as_str(),
+                    )
+                }
+                // This may seem stupid but freebsd can't create a vlan interface
+                // that is not named vlan<X> or <interface>.<X>
+                // however once created it happiely renames it ...
+                script.push_str(
+                    format!(
+                        "/sbin/ifconfig {iface}p.{vlan} create vlan {vlan} vlandev {iface}p; \
+                        /sbin/ifconfig {iface}p.{vlan} name {iface}; ",
+                        iface = self.interface,
+                        vlan = vlan
+                    ).as_str(),
+                );
+                script
+            }
+            None => {
+                format!(
+                    "/sbin/ifconfig {epair}b name {iface}; ",
+                    epair = epair,
+                    iface = self.interface
+                )
+            }
+        };
+        let mut link = String::new();
+        if !self.mac.is_empty() {
+            link.push_str(" ether ");
+            link.push_str(self.mac.as_str());
+        }
+        link.push_str(mtu.as_str());
+        if !link.is_empty() {
+            script.push_str(
+                format!("/sbin/ifconfig {}{}; ", self.interface, link).as_str(),
+            );
+        }
+        script.push_str(self.addr_script().as_str());
+        script
+    }
+
+    /// Commands to configure the addresses and routes of the interface
+    /// once it got its final name inside the jail
+    pub fn addr_script(&self) -> String {
+        let mut script = String::new();
+        let mut inet = false;
+        let mut inet6 = false;
+        for addr in self.addresses() {
+            if addr == "dhcp" {
+                // dhclient takes care of the address as well as the
+                // default route
+                script.push_str(format!("/sbin/dhclient {}; ", self.interface).as_str());
+            } else if addr == "addrconf" {
+                script.push_str(
+                    format!(
+                        "/sbin/ifconfig {iface} inet6 -ifdisabled accept_rtadv auto_linklocal; \
+                        /sbin/rtsol {iface}; ",
+                        iface = self.interface
+                    ).as_str(),
+                )
+            } else if addr.contains(':') {
+                // every address after the first one is added as an alias
+                script.push_str(
+                    format!(
+                        "/sbin/ifconfig {iface} inet6 -ifdisabled {addr}{alias}; ",
+                        iface = self.interface,
+                        addr = addr,
+                        alias = if inet6 { " alias" } else { "" }
+                    ).as_str(),
+                );
+                inet6 = true;
+            } else {
+                script.push_str(
+                    format!(
+                        "/sbin/ifconfig {iface} inet {addr}{alias}; ",
+                        iface = self.interface,
+                        addr = addr,
+                        alias = if inet { " alias" } else { "" }
+                    ).as_str(),
+                );
+                inet = true;
+            }
+        }
+        if self.primary {
+            match self.gateway {
+                Some(ref gateway) if !self.is_dhcp() => {
+                    let route = format!("/sbin/route add default -gateway {}; ", gateway);
+                    script.push_str(route.as_str());
+                }
+                _ => (),
+            }
+            if let Some(ref gateway6) = self.gateway6 {
+                // link local gateways are only reachable through the
+                // interface so we need to scope them.
+                let route6 = if gateway6.starts_with("fe80:") && !gateway6.contains('%') {
+                    format!(
+                        "/sbin/route -6 add default {}%{}; ",
+                        gateway6,
+                        self.interface
+                    )
+                } else {
+                    format!("/sbin/route -6 add default {}; ", gateway6)
+                };
+                script.push_str(route6.as_str());
+            }
+        }
+        script
+    }
+
+    /// The addresses of the nic, either taken from `ips` or build
+    /// from the single `ip`, `netmask` and `ip6` fields.
+    pub fn addresses(&self) -> Vec<String> {
+        if !self.ips.is_empty() {
+            return self.ips.clone();
+        }
+        let mut addresses = Vec::new();
+        if self.is_dhcp() {
+            addresses.push(self.ip.clone());
+        } else if !self.ip.is_empty() {
+            match self.netmask.as_ref().and_then(|mask| netmask_to_prefix(mask)) {
+                Some(prefix) => addresses.push(format!("{}/{}", self.ip, prefix)),
+                None => addresses.push(self.ip.clone()),
+            }
+        }
+        if let Some(ref ip6) = self.ip6 {
+            addresses.push(ip6.clone());
+        }
+        addresses
+    }
+
+    /// The static addresses of the nic without prefix length
+    pub fn static_ips(&self) -> Vec<String> {
+        self.addresses()
+            .iter()
+            .filter(|ip| *ip != "dhcp" && *ip != "addrconf")
+            .map(|ip| String::from(ip.split('/').next().unwrap_or("")))
+            .collect()
+    }
+
+    /// Brings the SmartOS `ips`/`gateways` lists and the single
+    /// `ip`/`netmask`/`gateway` fields in line with each other. The
+    /// lists are normalised and the single fields are taken from the
+    /// first matching entry.
+    pub fn normalize(&mut self) {
+        if self.ips.is_empty() {
+            if !self.ip.is_empty() && !self.is_dhcp() &&
+                self.netmask.as_ref().and_then(|mask| netmask_to_prefix(mask)).is_none()
+            {
+                // We leave invalid legacy values alone so they are
+                // reported by the validation.
+                return;
+            }
+            self.ips = self.addresses();
+        } else {
+            self.ips = self.ips
+                .iter()
+                .map(|ip| normalize_cidr(ip).unwrap_or_else(|| ip.clone()))
+                .collect();
+        }
+        if self.gateways.is_empty() {
+            if let Some(ref gateway) = self.gateway {
+                self.gateways.push(gateway.clone());
+            }
+            if let Some(ref gateway6) = self.gateway6 {
+                self.gateways.push(gateway6.clone());
+            }
+        }
+
+        self.ip = String::new();
+        self.netmask = None;
+        self.ip6 = None;
+        for ip in self.ips.iter() {
+            if ip == "dhcp" || (!ip.contains(':') && ip != "addrconf") {
+                if self.ip.is_empty() {
+                    let mut parts = ip.splitn(2, '/');
+                    self.ip = String::from(parts.next().unwrap_or(""));
+                    self.netmask = parts
+                        .next()
+                        .and_then(|prefix| prefix.parse::<u8>().ok())
+                        .and_then(prefix_to_netmask);
+                }
+            } else if self.ip6.is_none() {
+                self.ip6 = Some(ip.clone());
+            }
+        }
+        self.gateway = None;
+        self.gateway6 = None;
+        for gateway in self.gateways.iter() {
+            if gateway.contains(':') {
+                if self.gateway6.is_none() {
+                    self.gateway6 = Some(gateway.clone());
+                }
+            } else if self.gateway.is_none() {
+                self.gateway = Some(gateway.clone());
+            }
+        }
+    }
+
+    /// checks the nic for errors, `path` is the path of the nic in the
+    /// payload (like `nics.0`) and used to report the fields.
+    pub fn errors(&self, config: &Config, path: &str) -> Vec<ValidationError> {
+        let mut errors = Vec::new();
+        let field = |name: &str| format!("{}.{}", path, name);
+        // only check gateways against the subnets if all addresses are
+        // valid, otherwise we just report the invalid address
+        let addresses_valid = self.addresses().iter().all(|ip| ips_error(ip).is_none());
+        if let Some(error) = interface_error(self.interface.as_str()) {
+            errors.push(ValidationError::new(field("interface").as_str(), error))
+        }
+        if self.addresses().is_empty() {
+            errors.push(ValidationError::new(field("ip").as_str(), "Missing ip"))
+        }
+        // with DHCP netmask and gateway are handed out by the server
+        if !self.ip.is_empty() && !self.is_dhcp() {
+            if let Some(error) = ip_error(self.ip.as_str()) {
+                errors.push(ValidationError::new(field("ip").as_str(), error))
+            }
+            let netmask = self.netmask.as_ref().map_or("", |mask| mask.as_str());
+            if let Some(error) = netmask_error(netmask) {
+                errors.push(ValidationError::new(field("netmask").as_str(), error))
+            }
+        }
+        // static routes are only set up for the primary nic
+        if self.primary && self.gateway.is_none() && !self.is_dhcp() &&
+            self.addresses().iter().any(|ip| is_ip4_cidr(ip))
+        {
+            errors.push(ValidationError::new(
+                field("gateway").as_str(),
+                "Primary nic needs a gateway",
+            ))
+        }
+        if let Some(ref gateway) = self.gateway {
+            if let Some(error) = gateway_error(gateway.as_str()) {
+                errors.push(ValidationError::new(field("gateway").as_str(), error))
+            } else if addresses_valid && !self.is_dhcp() && !self.on_link(gateway) {
+                errors.push(ValidationError::new(
+                    field("gateway").as_str(),
+                    "Gateway is outside of the nic's subnets",
+                ))
+            }
+        }
+        if let Some(ref ip6) = self.ip6 {
+            if let Some(error) = ip6_error(ip6.as_str()) {
+                errors.push(ValidationError::new(field("ip6").as_str(), error))
+            }
+        }
+        if let Some(ref gateway6) = self.gateway6 {
+            if let Some(error) = gateway6_error(gateway6.as_str()) {
+                errors.push(ValidationError::new(field("gateway6").as_str(), error))
+            } else if addresses_valid && !self.on_link(gateway6) {
+                errors.push(ValidationError::new(
+                    field("gateway6").as_str(),
+                    "Gateway is outside of the nic's subnets",
+                ))
+            }
+        }
+        errors.append(&mut list_errors(field("ips").as_str(), &self.ips, ips_error));
+        errors.append(&mut list_errors(
+            field("gateways").as_str(),
+            &self.gateways,
+            gateways_error,
+        ));
+        if !self.mac.is_empty() {
+            if let Some(error) = mac_error(self.mac.as_str()) {
+                errors.push(ValidationError::new(field("mac").as_str(), error))
+            }
+        }
+        if let Some(error) = nic_tag_error(config, self.nic_tag.as_str()) {
+            errors.push(ValidationError::new(field("nic_tag").as_str(), error))
+        }
+        if let Some(error) = self.vlan.and_then(vlan_error) {
+            errors.push(ValidationError::new(field("vlan").as_str(), error))
+        }
+        if let Some(mtu) = self.mtu {
+            if let Some(error) = mtu_error(mtu) {
+                errors.push(ValidationError::new(field("mtu").as_str(), error))
+            } else if mtu < 1280 && self.addresses().iter().any(|ip| ip.contains(':')) {
+                errors.push(ValidationError::new(
+                    field("mtu").as_str(),
+                    "MTU must be at least 1280 for IPv6",
+                ))
+            }
+        }
+        errors
+    }
+
+    /// Checks if a gateway can be reached from one of the static
+    /// addresses of the nic, IPv6 link local gateways always can.
+    fn on_link(&self, gateway: &str) -> bool {
+        if let Ok(gw) = gateway.parse::<Ipv4Addr>() {
+            let gw = u32::from(gw);
+            return self.addresses().iter().any(|ip| match parse_ip4_cidr(ip) {
+                Some((addr, prefix)) => {
+                    let mask = prefix_to_mask(prefix);
+                    addr & mask == gw & mask
+                }
+                None => false,
+            });
+        }
+        let addr = match gateway.find('%') {
+            Some(idx) => &gateway[..idx],
+            None => gateway,
+        };
+        if let Ok(gw) = addr.parse::<Ipv6Addr>() {
+            if gw.segments()[0] & 0xffc0 == 0xfe80 {
+                return true;
+            }
+            return self.addresses().iter().any(|ip| match parse_ip6_cidr(ip) {
+                Some((addr, prefix)) => same_ip6_net(&addr, &gw, prefix),
+                None => false,
+            });
+        }
+        false
+    }
+
+    /// If the IPv4 address of the nic is configured by DHCP
+    pub fn is_dhcp(&self) -> bool {
+        self.ip == "dhcp"
+    }
 }
 
 /// Jail configuration values
@@ -211,27 +530,110 @@ pub struct JailConfig {
     // Resources
     /// max physical memory in MB (memoryuse)
     pub max_physical_memory: u64,
-    /// mac cpu usage 100 = 1 core (pcpu)
+    /// max cpu usage in percent, 100 = 1 core and 0 for no limit (pcpu)
     pub cpu_cap: u64,
     /// max quota (zfs quota)
     pub quota: u64,
 
-    /// SysV shared memory size, in bytes (shmsize)
+    /// SysV shared memory size in MB, defaults to max_physical_memory
+    /// (shmsize)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub max_shm_memory: Option<u64>,
 
-    /// locked memory (memorylocked)
+    /// locked memory in MB, defaults to max_physical_memory
+    /// (memorylocked)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub max_locked_memory: Option<u64>,
 
+    /// cpus the jail is pinned to, in cpuset(1) list syntax (`0-3,6`),
+    /// payloads can give a list of cpu ids as well
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpuset: Option<String>,
+
+    /// additional rctl rules per resource, like a `log` action at
+    /// 90% of the memory limit
+    #[serde(default = "empty_thresholds", skip_serializing_if = "Map::is_empty")]
+    pub thresholds: Map<String, Vec<Threshold>>,
+
+    /// command the inner jail is started with, `sh /etc/rc` if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub init_name: Option<String>,
+
+    /// command run in the inner jail when it is stopped
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_command: Option<String>,
+
+    /// environment variables for the start and stop command
+    #[serde(default = "empty_env", skip_serializing_if = "Map::is_empty")]
+    pub exec_env: Map<String, String>,
+
+    /// hooks run for lifecycle events of the jail, per event
+    #[serde(default = "empty_hooks", skip_serializing_if = "Map::is_empty")]
+    pub hooks: Map<String, Vec<String>>,
+
+    /// additional device patterns to unhide in the jails devfs, like
+    /// `bpf*` or `tun*`
+    #[serde(default = "empty_devfs", skip_serializing_if = "Vec::is_empty")]
+    pub devfs: Vec<String>,
+
+    /// jail(8) parameters of the outer and inner jail, they overwrite
+    /// the defaults from the vmadm.toml
+    #[serde(default = "empty_jail_params", skip_serializing_if = "JailParams::is_empty")]
+    pub jail_params: JailParams,
+
     /// networks
     #[serde(default = "empty_nics")]
     pub nics: Vec<NIC>,
 
+    /// static routes, maps a destination address or CIDR to a
+    /// gateway or a nic in the form `nics[<index>]`
+    #[serde(default = "empty_routes", skip_serializing_if = "Map::is_empty")]
+    pub routes: Map<String, String>,
+
     /// maximum number of porocesses (maxproc)
     #[serde(default = "dflt_max_lwp")]
     pub max_lwps: u64,
 
+    /// max swap in MB (swapuse)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_swap: Option<u64>,
+
+    /// max virtual memory in MB (vmemoryuse)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_vmemory: Option<u64>,
+
+    /// maximum number of open files (openfiles)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_open_files: Option<u64>,
+
+    /// disk read bytes per second (readbps)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_read_bps: Option<u64>,
+
+    /// disk write bytes per second (writebps)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_write_bps: Option<u64>,
+
+    /// disk read operations per second (readiops)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_read_iops: Option<u64>,
+
+    /// disk write operations per second (writeiops)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_write_iops: Option<u64>,
+
+    /// maximum number of SysV message queues (nmsgq)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_msg_ids: Option<u64>,
+
+    /// maximum number of SysV semaphores (nsem)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_sem_ids: Option<u64>,
+
+    /// maximum number of SysV shared memory segments (nshm)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_shm_ids: Option<u64>,
+
     // Metadata fields w/o effect on vmadm at the moment
     /// Should be archived when deleted
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -284,7 +686,26 @@ impl PartialEq for JailConfig {
             self.max_shm_memory == other.max_shm_memory &&
             self.max_locked_memory == other.max_locked_memory &&
             self.nics == other.nics &&
+            self.routes == other.routes &&
             self.max_lwps == other.max_lwps &&
+            self.thresholds == other.thresholds &&
+            self.cpuset == other.cpuset &&
+            self.jail_params == other.jail_params &&
+            self.devfs == other.devfs &&
+            self.init_name == other.init_name &&
+            self.stop_command == other.stop_command &&
+            self.exec_env == other.exec_env &&
+            self.hooks == other.hooks &&
+            self.max_swap == other.max_swap &&
+            self.max_vmemory == other.max_vmemory &&
+            self.max_open_files == other.max_open_files &&
+            self.max_read_bps == other.max_read_bps &&
+            self.max_write_bps == other.max_write_bps &&
+            self.max_read_iops == other.max_read_iops &&
+            self.max_write_iops == other.max_write_iops &&
+            self.max_msg_ids == other.max_msg_ids &&
+            self.max_sem_ids == other.max_sem_ids &&
+            self.max_shm_ids == other.max_shm_ids &&
             self.archive_on_delete == other.archive_on_delete &&
             self.billing_id == other.billing_id &&
             self.do_not_inventory == other.do_not_inventory &&
@@ -298,38 +719,214 @@ impl PartialEq for JailConfig {
 }
 
 lazy_static! {
-  static ref HOSTNAME_RE: Regex = Regex::new("^[a-zA-Z0-9]([a-zA-Z0-9-]{0,253}[a-zA-Z0-9])?$").unwrap();
-  static ref ALIAS_RE: Regex = Regex::new("^[a-zA-Z0-9]([a-zA-Z0-9-]{0,253}[a-zA-Z0-9])?$").unwrap();
-  static ref INTERFACE_RE: Regex = Regex::new("^[a-zA-Z]{1,4}[0-9]{0,3}$").unwrap();
-  static ref IP_RE: Regex = Regex::new("^(([0-9]|[1-9][0-9]|1[0-9]{2}|2[0-4][0-9]|25[0-5])\\.){3}([0-9]|[1-9][0-9]|1[0-9]{2}|2[0-4][0-9]|25[0-5])$").unwrap();
-  static ref MAC_RE: Regex = Regex::new("^[a-fA-F0-9]{1,2}([:][a-fA-F0-9]{1,2}){5}$").unwrap();
+  pub static ref HOSTNAME_RE: Regex = Regex::new("^[a-zA-Z0-9]([a-zA-Z0-9-]{0,253}[a-zA-Z0-9])?$").unwrap();
+  pub static ref ALIAS_RE: Regex = Regex::new("^[a-zA-Z0-9]([a-zA-Z0-9-]{0,253}[a-zA-Z0-9])?$").unwrap();
+  pub static ref INTERFACE_RE: Regex = Regex::new("^[a-z]{1,8}[0-9]{1,3}$").unwrap();
+  pub static ref IP_RE: Regex = Regex::new("^(([0-9]|[1-9][0-9]|1[0-9]{2}|2[0-4][0-9]|25[0-5])\\.){3}([0-9]|[1-9][0-9]|1[0-9]{2}|2[0-4][0-9]|25[0-5])$").unwrap();
+  /// number of cpus of the host, if it can be determined
+  pub static ref HOST_CPUS: Option<u64> = host_cpus();
+  static ref DEVFS_RE: Regex = Regex::new("^[a-zA-Z0-9_.*?/\\[\\]-]+$").unwrap();
+  static ref ENV_RE: Regex = Regex::new("^[a-zA-Z_][a-zA-Z0-9_]*$").unwrap();
+  static ref AMOUNT_RE: Regex = Regex::new("^[0-9]+[kKmMgGtTpPeE]?$").unwrap();
+  pub static ref MAC_RE: Regex = Regex::new("^[a-fA-F0-9]{1,2}([:][a-fA-F0-9]{1,2}){5}$").unwrap();
+}
+
+/// Properties a jail can have
+pub static CONFIG_KEYS: &'static [&'static str] = &[
+    "brand",
+    "uuid",
+    "image_uuid",
+    "alias",
+    "hostname",
+    "autostart",
+    "max_physical_memory",
+    "cpu_cap",
+    "quota",
+    "max_shm_memory",
+    "max_locked_memory",
+    "nics",
+    "routes",
+    "max_lwps",
+    "thresholds",
+    "cpuset",
+    "jail_params",
+    "devfs",
+    "init_name",
+    "stop_command",
+    "exec_env",
+    "hooks",
+    "max_swap",
+    "max_vmemory",
+    "max_open_files",
+    "max_read_bps",
+    "max_write_bps",
+    "max_read_iops",
+    "max_write_iops",
+    "max_msg_ids",
+    "max_sem_ids",
+    "max_shm_ids",
+    "archive_on_delete",
+    "billing_id",
+    "do_not_inventory",
+    "dns_domain",
+    "indestructible_delegated",
+    "indestructible_zoneroot",
+    "owner_uuid",
+    "package_name",
+    "package_version",
+];
+
+/// Properties a nic can have
+static NIC_KEYS: &'static [&'static str] = &[
+    "interface",
+    "mac",
+    "vlan",
+    "nic_tag",
+    "ip",
+    "netmask",
+    "gateway",
+    "ip6",
+    "gateway6",
+    "ips",
+    "gateways",
+    "primary",
+    "mtu",
+    "network_uuid",
+];
+
+/// SmartOS properties vmadm does not support (yet)
+static UNSUPPORTED_KEYS: &'static [&'static str] = &[
+    "cpu_shares",
+    "customer_metadata",
+    "delegate_dataset",
+    "disks",
+    "docker",
+    "filesystems",
+    "firewall_enabled",
+    "fs_allowed",
+    "internal_metadata",
+    "internal_metadata_namespaces",
+    "kernel_version",
+    "limit_priv",
+    "maintain_resolvers",
+    "mdata_exec_timeout",
+    "nowait",
+    "ram",
+    "resolvers",
+    "tags",
+    "tmpfs",
+    "vcpus",
+    "zfs_data_compression",
+    "zfs_data_recsize",
+    "zfs_filesystem_limit",
+    "zfs_io_priority",
+    "zfs_root_compression",
+    "zfs_root_recsize",
+    "zfs_snapshot_limit",
+    "zonename",
+];
+
+/// Reports the properties of a JSON object that are not in `known`,
+/// `prefix` is the path of the object in the payload.
+pub fn unknown_keys(
+    value: &serde_json::Value,
+    known: &[&str],
+    prefix: &str,
+) -> Vec<ValidationError> {
+    let mut errors = Vec::new();
+    if let Some(object) = value.as_object() {
+        for key in object.keys() {
+            if known.contains(&key.as_str()) {
+                continue;
+            }
+            let field = if prefix.is_empty() {
+                key.clone()
+            } else {
+                format!("{}.{}", prefix, key)
+            };
+            let error = if UNSUPPORTED_KEYS.contains(&key.as_str()) {
+                "Unsupported property"
+            } else {
+                "Unknown property"
+            };
+            errors.push(ValidationError::new(field.as_str(), error))
+        }
+    }
+    errors
+}
+
+/// Reports properties of the nics in the list `field` of a JSON
+/// document that are not known.
+pub fn unknown_nic_keys(value: &serde_json::Value, field: &str) -> Vec<ValidationError> {
+    let mut errors = Vec::new();
+    if let Some(nics) = value.get(field).and_then(|nics| nics.as_array()) {
+        for (i, nic) in nics.iter().enumerate() {
+            errors.append(&mut unknown_keys(
+                nic,
+                NIC_KEYS,
+                format!("{}.{}", field, i).as_str(),
+            ));
+        }
+    }
+    errors
+}
+
+/// Handles properties that are ignored when reading a payload, in
+/// strict mode they are returned as errors otherwise they are listed
+/// as warnings on stderr.
+pub fn ignored_keys(config: &Config, ignored: Vec<ValidationError>) -> Vec<ValidationError> {
+    if config.settings.strict {
+        return ignored;
+    }
+    for e in ignored.iter() {
+        warn!("Ignoring property"; "field" => e.field(), "error" => e.to_string());
+        eprintln!("warning: ignoring {}", e);
+    }
+    Vec::new()
 }
 
 impl JailConfig {
-    /// Reads a new config from a file
-    pub fn from_file(config: &Config, config_path: &str) -> Result<Self, Box<Error>> {
+    /// Reads a stored config from a file, stored configs were checked
+    /// when they were written and are not validated again so that
+    /// stricter checks don't lock out existing jails.
+    pub fn from_file(config_path: &str) -> Result<Self, Box<Error>> {
         let config_file = File::open(config_path)?;
-        JailConfig::from_reader(config, config_file)
+        let mut conf: JailConfig = serde_json::from_reader(config_file)?;
+        conf.normalize();
+        Ok(conf)
     }
 
-    /// Reads the config from a reader
+    /// Reads and validates a create payload from a reader
     pub fn from_reader<R>(config: &Config, reader: R) -> Result<Self, Box<Error>>
     where
         R: Read,
     {
-        let mut conf: JailConfig = serde_json::from_reader(reader)?;
-        let max_physical_memory = conf.max_physical_memory;
-        if conf.max_shm_memory.is_none() {
-            conf.max_shm_memory = Some(max_physical_memory);
-        }
-        if conf.max_locked_memory.is_none() {
-            conf.max_locked_memory = Some(max_physical_memory);
-        }
+        let mut value: serde_json::Value = serde_json::from_reader(reader)?;
+        let mut unknown = cpuset_from_ids(&mut value);
+        unknown.append(&mut rename_alias(&mut value, "boot_command", "init_name"));
+        unknown.append(&mut unknown_nic_keys(&value, "nics"));
+        unknown.append(&mut ignored_keys(
+            config,
+            unknown_keys(&value, CONFIG_KEYS, ""),
+        ));
+        let mut conf: JailConfig = serde_json::from_value(value)?;
+        conf.normalize();
         match conf.errors(config) {
-            Some(errors) => Err(ValidationErrors::bx(errors)),
+            Some(mut errors) => {
+                errors.extend(unknown);
+                Err(ValidationErrors::bx(errors))
+            }
+            None if !unknown.is_empty() => Err(ValidationErrors::bx(unknown)),
             None => Ok(conf),
         }
     }
+    /// Normalises the network configuration of all nics
+    pub fn normalize(&mut self) {
+        for nic in self.nics.iter_mut() {
+            nic.normalize();
+        }
+    }
+
     /// checks the config for errors
     pub fn errors(&self, config: &Config) -> Option<Vec<ValidationError>> {
         let mut errors = Vec::new();
@@ -339,46 +936,110 @@ impl JailConfig {
         if !ALIAS_RE.is_match(self.alias.as_str()) {
             errors.push(ValidationError::new("alias", "Invalid alias"))
         }
-        let mut i = 0;
-        for nic in self.nics.clone() {
-            if !INTERFACE_RE.is_match(nic.interface.as_str()) {
-                errors.push(ValidationError::new(
-                    format!("nic[{}]", i).as_str(),
-                    "Invalid interface name",
-                ))
+        for &(field, limit, max, unit) in [
+            ("max_swap", self.max_swap, MAX_MEMORY, " MB"),
+            ("max_vmemory", self.max_vmemory, MAX_MEMORY, " MB"),
+            ("max_open_files", self.max_open_files, MAX_COUNT, ""),
+            ("max_read_bps", self.max_read_bps, MAX_BPS, " bytes/s"),
+            ("max_write_bps", self.max_write_bps, MAX_BPS, " bytes/s"),
+            ("max_read_iops", self.max_read_iops, MAX_IOPS, ""),
+            ("max_write_iops", self.max_write_iops, MAX_IOPS, ""),
+            ("max_msg_ids", self.max_msg_ids, MAX_COUNT, ""),
+            ("max_sem_ids", self.max_sem_ids, MAX_COUNT, ""),
+            ("max_shm_ids", self.max_shm_ids, MAX_COUNT, ""),
+        ].iter()
+        {
+            match limit {
+                Some(limit) if limit == 0 || limit > max => {
+                    errors.push(ValidationError::new(
+                        field,
+                        format!("Limit must be between 1 and {}{}", max, unit).as_str(),
+                    ))
+                }
+                _ => (),
             }
-            if !IP_RE.is_match(nic.ip.as_str()) {
-                errors.push(ValidationError::new(
-                    format!("nic[{}]", i).as_str(),
-                    "Invalid ip",
-                ))
+        }
+        if let Some(ref cpuset) = self.cpuset {
+            if let Some(error) = cpuset_error(cpuset.as_str(), *HOST_CPUS) {
+                errors.push(ValidationError::new("cpuset", error))
             }
-            if !IP_RE.is_match(nic.netmask.as_str()) {
+        }
+        for &(field, ref command) in [
+            ("init_name", &self.init_name),
+            ("stop_command", &self.stop_command),
+        ].iter()
+        {
+            if let Some(ref command) = **command {
+                if let Some(error) = command_error(command.as_str()) {
+                    errors.push(ValidationError::new(field, error))
+                }
+            }
+        }
+        errors.append(&mut env_errors("exec_env", &self.exec_env));
+        errors.append(&mut hooks::errors("hooks", &self.hooks));
+        for (i, pattern) in self.devfs.iter().enumerate() {
+            if let Some(error) = devfs_error(pattern.as_str()) {
+                errors.push(ValidationError::new(format!("devfs.{}", i).as_str(), error))
+            }
+        }
+        errors.append(&mut self.jail_params.errors("jail_params"));
+        match self.jail_params(config).outer.get("children.max") {
+            Some(&JailParam::Int(n)) if n >= 1 => (),
+            _ => {
                 errors.push(ValidationError::new(
-                    format!("nic[{}]", i).as_str(),
-                    "Invalid netmask",
+                    "jail_params.outer.children.max",
+                    "The outer jail needs children.max of at least 1",
                 ))
             }
-            if !IP_RE.is_match(nic.gateway.as_str()) {
+        }
+        let limited: Vec<&str> = self.deny_limits().iter().map(|&(r, _, _)| r).collect();
+        errors.append(&mut thresholds_errors("thresholds", &self.thresholds, Some(&limited)));
+        match self.max_swap {
+            Some(max_swap) if max_swap > 0 && max_swap < self.max_physical_memory => {
                 errors.push(ValidationError::new(
-                    format!("nic[{}]", i).as_str(),
-                    "Invalid gateway",
+                    "max_swap",
+                    "max_swap must be at least max_physical_memory",
                 ))
             }
-            if !MAC_RE.is_match(nic.mac.as_str()) {
+            _ => (),
+        }
+        let mut primary = false;
+        for (i, nic) in self.nics.iter().enumerate() {
+            errors.append(&mut nic.errors(config, format!("nics.{}", i).as_str()));
+            let before = &self.nics[..i];
+            if before.iter().any(|other| other.interface == nic.interface) {
                 errors.push(ValidationError::new(
-                    format!("nic[{}]", i).as_str(),
-                    "Invalid mac",
+                    format!("nics.{}.interface", i).as_str(),
+                    "Duplicate interface name",
                 ))
             }
-            if !config.settings.networks.contains_key(&nic.nic_tag) {
+            if !nic.mac.is_empty() &&
+                before.iter().any(|other| {
+                    other.mac.to_lowercase() == nic.mac.to_lowercase()
+                })
+            {
                 errors.push(ValidationError::new(
-                    format!("nic[{}]", i).as_str(),
-                    "Unknown nic_tag",
+                    format!("nics.{}.mac", i).as_str(),
+                    "Duplicate mac",
                 ))
-
             }
-            i = i + 1;
+            if nic.primary {
+                if primary {
+                    errors.push(ValidationError::new(
+                        format!("nics.{}.primary", i).as_str(),
+                        "Only one nic can be primary",
+                    ))
+                }
+                primary = true;
+            }
+        }
+        for (dst, gateway) in self.routes.iter() {
+            errors.append(&mut route_errors(
+                format!("routes.{}", dst).as_str(),
+                dst,
+                gateway,
+                Some(self.nics.len()),
+            ));
         }
         if errors.is_empty() {
             None
@@ -388,8 +1049,173 @@ impl JailConfig {
 
     }
 
-    /// Translates the config into resource controle limts
-    pub fn rctl_limits(&self) -> Vec<String> {
+    /// The changes from `old` to this config, nics are matched by
+    /// their mac.
+    pub fn diff(&self, old: &JailConfig) -> Vec<Change> {
+        let old_value = serde_json::to_value(old).unwrap_or(serde_json::Value::Null);
+        let new_value = serde_json::to_value(self).unwrap_or(serde_json::Value::Null);
+        let mut res: Vec<Change> = object_changes("", &old_value, &new_value)
+            .into_iter()
+            .filter(|change| change.field != "nics")
+            .map(|mut change| {
+                change.apply = apply_kind(change.field.as_str());
+                change
+            })
+            .collect();
+        for nic in old.nics.iter() {
+            if !self.nics.iter().any(|n| n.mac == nic.mac) {
+                res.push(Change {
+                    field: format!("nics.{}", nic.mac),
+                    old: serde_json::to_value(nic).ok(),
+                    new: None,
+                    apply: Apply::Nics,
+                });
+            }
+        }
+        // added and removed nics are applied live, changed ones are not
+        for nic in self.nics.iter() {
+            let new_nic = serde_json::to_value(nic).unwrap_or(serde_json::Value::Null);
+            match old.nics.iter().find(|o| o.mac == nic.mac) {
+                None => {
+                    res.push(Change {
+                        field: format!("nics.{}", nic.mac),
+                        old: None,
+                        new: Some(new_nic),
+                        apply: Apply::Nics,
+                    })
+                }
+                Some(old_nic) => {
+                    let old_nic = serde_json::to_value(old_nic).unwrap_or(serde_json::Value::Null);
+                    let prefix = format!("nics.{}", nic.mac);
+                    for mut change in object_changes(prefix.as_str(), &old_nic, &new_nic) {
+                        change.apply = Apply::Restart;
+                        res.push(change);
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    /// The jail(8) parameters of the outer and inner jail, the defaults
+    /// of vmadm are overwritten by the `jail_params` of the vmadm.toml
+    /// and those by the ones of the jail.
+    pub fn jail_params(&self, config: &Config) -> JailParams {
+        let mut params = JailParams::defaults(config);
+        params.merge(&config.settings.jail_params);
+        params.merge(&self.jail_params);
+        params
+    }
+
+    /// The devices to unhide in the jails devfs, dhclient needs `bpf`
+    /// for DHCP nics.
+    pub fn devfs_patterns(&self) -> Vec<String> {
+        let mut patterns = self.devfs.clone();
+        if self.nics.iter().any(|nic| nic.is_dhcp()) && !patterns.iter().any(|p| p == "bpf*") {
+            patterns.push(String::from("bpf*"));
+        }
+        patterns
+    }
+
+    /// The rules of the devfs ruleset of the jail as arguments to
+    /// `devfs rule`, the standard ruleset is included and the `devfs`
+    /// patterns are unhidden on top of it.
+    pub fn devfs_rules(&self, config: &Config) -> Vec<Vec<String>> {
+        let mut rules = vec![
+            vec![
+                String::from("add"),
+                String::from("include"),
+                config.settings.devfs_ruleset.to_string(),
+            ],
+        ];
+        for pattern in self.devfs_patterns() {
+            rules.push(vec![
+                String::from("add"),
+                String::from("path"),
+                pattern.clone(),
+                String::from("unhide"),
+            ]);
+        }
+        rules
+    }
+
+    /// Properties that changed compared to `old` and only take effect
+    /// once the jail is restarted.
+    pub fn reboot_changes(&self, old: &JailConfig) -> Vec<String> {
+        self.diff(old)
+            .into_iter()
+            .filter(|change| !change.live())
+            .map(|change| change.field)
+            .collect()
+    }
+
+    /// Arguments to `route` to add or delete the route to a given
+    /// destination.
+    pub fn route_args(&self, command: &str, dst: &str) -> Vec<String> {
+        let mut args = vec![String::from(command)];
+        if dst.contains(':') {
+            args.push(String::from("-inet6"));
+        }
+        if dst.contains('/') {
+            args.push(String::from("-net"));
+        } else {
+            args.push(String::from("-host"));
+        }
+        args.push(String::from(dst));
+        if command == "delete" {
+            return args;
+        }
+        if let Some(gateway) = self.routes.get(dst) {
+            match nic_ref(gateway).and_then(|idx| self.nics.get(idx)) {
+                Some(nic) => {
+                    args.push(String::from("-interface"));
+                    args.push(nic.interface.clone());
+                }
+                None => args.push(gateway.clone()),
+            }
+        }
+        args
+    }
+
+    /// Commands to add the static routes once the interfaces are up
+    pub fn route_script(&self) -> String {
+        let mut script = String::new();
+        for dst in self.routes.keys() {
+            script.push_str("/sbin/route ");
+            script.push_str(self.route_args("add", dst).join(" ").as_str());
+            script.push_str("; ");
+        }
+        script
+    }
+
+    /// The command the inner jail is started with, `exec_env` is
+    /// exported before it.
+    pub fn exec_start(&self) -> String {
+        match self.init_name {
+            Some(ref init_name) => self.with_env(init_name.as_str()),
+            None => self.with_env("sh /etc/rc"),
+        }
+    }
+
+    /// The command run in the inner jail when it is stopped
+    pub fn exec_stop(&self) -> Option<String> {
+        self.stop_command.as_ref().map(
+            |command| self.with_env(command.as_str()),
+        )
+    }
+
+    fn with_env(&self, command: &str) -> String {
+        let mut res = String::new();
+        for (name, value) in self.exec_env.iter() {
+            res.push_str(format!("export {}={}; ", name, shell_quote(value.as_str())).as_str());
+        }
+        res.push_str(command);
+        res
+    }
+
+    /// Translates the config into resource controle limts, the
+    /// thresholds of the jail replace the host defaults per resource.
+    pub fn rctl_limits(&self, config: &Config) -> Vec<String> {
         let mut res = Vec::new();
         let uuid = self.uuid.clone();
         let mut base = String::from("jail:");
@@ -397,45 +1223,907 @@ impl JailConfig {
 
         res.push(String::from("-a"));
 
-        let max_physical_memory = self.max_physical_memory.to_string();
-        let mut mem = base.clone();
-        mem.push_str(":memoryuse:deny=");
-        mem.push_str(max_physical_memory.as_str());
-        mem.push_str("M");
-        res.push(mem);
+        let mut thresholds = config.settings.thresholds.clone();
+        for (resource, list) in self.thresholds.iter() {
+            thresholds.insert(resource.clone(), list.clone());
+        }
 
-        let mut memorylocked = base.clone();
-        memorylocked.push_str(":memorylocked:deny=");
-        match self.max_locked_memory {
-            Some(max_locked_memory) => {
-                memorylocked.push_str(max_locked_memory.to_string().as_str())
+        let limits = self.deny_limits();
+        for &(resource, limit, unit) in limits.iter() {
+            // a deny threshold replaces the limit
+            let denied = thresholds.get(resource).map_or(false, |list| {
+                list.iter().any(|threshold| threshold.action == "deny")
+            });
+            if !denied {
+                res.push(format!("{}:{}:deny={}{}", base, resource, limit, unit));
+            }
+        }
+        for (resource, list) in thresholds.iter() {
+            let limit = limits.iter().find(|&&(r, _, _)| r == resource.as_str());
+            for threshold in list.iter() {
+                let amount = match (threshold.percent(), limit) {
+                    (Some(percent), Some(&(_, limit, unit))) => {
+                        format!("{}{}", limit * percent / 100, unit)
+                    }
+                    // without a limit there is nothing to take a percentage of
+                    (Some(_), None) => continue,
+                    (None, _) => threshold.amount.clone(),
+                };
+                res.push(format!("{}:{}:{}={}", base, resource, threshold.action, amount));
             }
-            None => memorylocked.push_str(max_physical_memory.as_str()),
         }
-        memorylocked.push_str("M");
-        res.push(memorylocked);
 
-        let mut shmsize = base.clone();
-        shmsize.push_str(":shmsize:deny=");
-        match self.max_shm_memory {
-            Some(max_shm_memory) => shmsize.push_str(max_shm_memory.to_string().as_str()),
-            None => shmsize.push_str(max_physical_memory.as_str()),
+        res
+    }
+
+    /// The deny limits as rctl resource, limit and unit suffix, memory
+    /// is given in MB.
+    fn deny_limits(&self) -> Vec<(&'static str, u64, &'static str)> {
+        let mut res = vec![
+            ("memoryuse", self.max_physical_memory, "M"),
+            (
+                "memorylocked",
+                self.max_locked_memory.unwrap_or(self.max_physical_memory),
+                "M",
+            ),
+            (
+                "shmsize",
+                self.max_shm_memory.unwrap_or(self.max_physical_memory),
+                "M",
+            ),
+            ("maxproc", self.max_lwps, ""),
+        ];
+        // like on SmartOS a cpu_cap of 0 means no cap
+        if self.cpu_cap > 0 {
+            res.push(("pcpu", self.cpu_cap, ""));
+        }
+        for &(resource, limit, unit) in self.optional_limits().iter() {
+            if let Some(limit) = limit {
+                res.push((resource, limit, unit));
+            }
         }
-        shmsize.push_str("M");
-        res.push(shmsize);
+        res
+    }
 
-        let mut pcpu = base.clone();
-        pcpu.push_str(":pcpu:deny=");
-        pcpu.push_str(self.cpu_cap.to_string().as_str());
-        res.push(pcpu);
+    /// The optional limits as rctl resource, limit and unit suffix
+    fn optional_limits(&self) -> Vec<(&'static str, Option<u64>, &'static str)> {
+        vec![
+            ("swapuse", self.max_swap, "M"),
+            ("vmemoryuse", self.max_vmemory, "M"),
+            ("openfiles", self.max_open_files, ""),
+            ("readbps", self.max_read_bps, ""),
+            ("writebps", self.max_write_bps, ""),
+            ("readiops", self.max_read_iops, ""),
+            ("writeiops", self.max_write_iops, ""),
+            ("nmsgq", self.max_msg_ids, ""),
+            ("nsem", self.max_sem_ids, ""),
+            ("nshm", self.max_shm_ids, ""),
+        ]
+    }
+}
 
+/// Upper bound of the memory limits in MB (16 TB), larger values most
+/// likely were given in bytes.
+const MAX_MEMORY: u64 = 16 * 1024 * 1024;
+/// Upper bound of the disk throughput limits (1 TB/s)
+const MAX_BPS: u64 = 1 << 40;
+/// Upper bound of the disk operation limits
+const MAX_IOPS: u64 = 100_000_000;
+/// Upper bound of the limits on a number of kernel objects
+const MAX_COUNT: u64 = 2_147_483_647;
 
-        let mut maxproc = base.clone();
-        maxproc.push_str(":maxproc:deny=");
-        maxproc.push_str(self.max_lwps.to_string().as_str());
-        res.push(maxproc);
+/// Highest number of cpus a cpuset may use if the host can't tell how
+/// many it has.
+const MAX_CPUS: u64 = 1024;
 
-        res
+/// Parses a cpu list like `0-3,6` into ranges of cpu ids, the ranges
+/// are not expanded so huge ones can be rejected.
+pub fn parse_cpuset(cpuset: &str) -> Option<Vec<(u64, u64)>> {
+    let mut res = Vec::new();
+    for part in cpuset.split(',') {
+        let mut bounds = part.splitn(2, '-');
+        let first: u64 = match bounds.next().and_then(|b| b.parse().ok()) {
+            Some(first) => first,
+            None => return None,
+        };
+        let last: u64 = match bounds.next() {
+            Some(last) => {
+                match last.parse() {
+                    Ok(last) if last >= first => last,
+                    _ => return None,
+                }
+            }
+            None => first,
+        };
+        res.push((first, last));
+    }
+    Some(res)
+}
+
+/// Checks a cpu list for errors, if the number of cpus of the host is
+/// known the cpus have to exist.
+pub fn cpuset_error(cpuset: &str, host_cpus: Option<u64>) -> Option<&'static str> {
+    let last = match parse_cpuset(cpuset) {
+        None => return Some("Invalid cpuset"),
+        Some(ranges) => ranges.iter().map(|&(_, last)| last).max().unwrap_or(0),
+    };
+    match host_cpus {
+        Some(host_cpus) if last >= host_cpus => Some("CPU not present on the host"),
+        None if last >= MAX_CPUS => Some("CPU ids must be below 1024"),
+        _ => None,
+    }
+}
+
+/// Turns a `cpuset` given as JSON array of cpu ids in a payload into
+/// the cpu list syntax.
+pub fn cpuset_from_ids(value: &mut serde_json::Value) -> Vec<ValidationError> {
+    let ids: Option<Vec<String>> = match value.get("cpuset").and_then(|v| v.as_array()) {
+        Some(ids) => ids.iter().map(|id| id.as_u64().map(|id| id.to_string())).collect(),
+        None => return Vec::new(),
+    };
+    let object = match value.as_object_mut() {
+        Some(object) => object,
+        None => return Vec::new(),
+    };
+    match ids {
+        Some(ids) => {
+            object.insert(String::from("cpuset"), serde_json::Value::String(ids.join(",")));
+            Vec::new()
+        }
+        None => {
+            // reported as validation error rather than a parse error
+            object.remove("cpuset");
+            vec![ValidationError::new("cpuset", "Invalid cpuset")]
+        }
+    }
+}
+
+/// Renames the property `alias` of a payload to `name`, both can't be
+/// given at the same time.
+pub fn rename_alias(
+    value: &mut serde_json::Value,
+    alias: &str,
+    name: &str,
+) -> Vec<ValidationError> {
+    let object = match value.as_object_mut() {
+        Some(object) => object,
+        None => return Vec::new(),
+    };
+    match object.remove(alias) {
+        Some(_) if object.contains_key(name) => {
+            let error = format!("Alias of {} which is given as well", name);
+            vec![ValidationError::new(alias, error.as_str())]
+        }
+        Some(aliased) => {
+            object.insert(String::from(name), aliased);
+            Vec::new()
+        }
+        None => Vec::new(),
+    }
+}
+
+#[cfg(target_os = "freebsd")]
+fn host_cpus() -> Option<u64> {
+    let output = match Command::new("sysctl").args(&["-n", "hw.ncpu"]).output() {
+        Ok(output) => output,
+        Err(_) => return None,
+    };
+    String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+}
+
+#[cfg(not(target_os = "freebsd"))]
+fn host_cpus() -> Option<u64> {
+    None
+}
+
+/// Quotes a string as a single word for sh(1)
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Checks a start or stop command
+pub fn command_error(command: &str) -> Option<&'static str> {
+    if command.trim().is_empty() {
+        Some("Command can not be empty")
+    } else if command.contains('\0') || command.contains('\n') {
+        Some("Invalid command")
+    } else {
+        None
+    }
+}
+
+/// Checks the names of environment variables
+pub fn env_errors(path: &str, env: &Map<String, String>) -> Vec<ValidationError> {
+    env.iter()
+        .filter(|&(name, value)| !ENV_RE.is_match(name.as_str()) || value.contains('\0'))
+        .map(|(name, _)| {
+            ValidationError::new(format!("{}.{}", path, name).as_str(), "Invalid variable")
+        })
+        .collect()
+}
+
+/// Checks a nic interface name, lo0 is already taken by the loopback
+/// interface.
+pub fn interface_error(interface: &str) -> Option<&'static str> {
+    if !INTERFACE_RE.is_match(interface) || interface.starts_with("lo") {
+        Some("Invalid interface name")
+    } else {
+        None
+    }
+}
+
+/// Checks a mac address
+pub fn mac_error(mac: &str) -> Option<&'static str> {
+    if MAC_RE.is_match(mac) {
+        None
+    } else {
+        Some("Invalid mac")
+    }
+}
+
+/// Checks the single IPv4 address of a nic
+pub fn ip_error(ip: &str) -> Option<&'static str> {
+    if ip == "dhcp" || IP_RE.is_match(ip) {
+        None
+    } else {
+        Some("Invalid ip")
+    }
+}
+
+/// Checks the netmask of a nic
+pub fn netmask_error(netmask: &str) -> Option<&'static str> {
+    match netmask_to_prefix(netmask) {
+        Some(_) => None,
+        None => Some("Invalid netmask"),
+    }
+}
+
+/// Checks the IPv4 gateway of a nic
+pub fn gateway_error(gateway: &str) -> Option<&'static str> {
+    if IP_RE.is_match(gateway) {
+        None
+    } else {
+        Some("Invalid gateway")
+    }
+}
+
+/// Checks the IPv6 address of a nic
+pub fn ip6_error(ip6: &str) -> Option<&'static str> {
+    if ip6 == "addrconf" || is_ip6_cidr(ip6) {
+        None
+    } else {
+        Some("Invalid ip6")
+    }
+}
+
+/// Checks the IPv6 gateway of a nic
+pub fn gateway6_error(gateway6: &str) -> Option<&'static str> {
+    if is_ip6(gateway6) {
+        None
+    } else {
+        Some("Invalid gateway6")
+    }
+}
+
+/// Checks an entry of the `ips` list of a nic
+pub fn ips_error(ip: &str) -> Option<&'static str> {
+    if ip == "dhcp" || ip == "addrconf" || is_ip4_cidr(ip) || is_ip6_cidr(ip) {
+        None
+    } else {
+        Some("Invalid ip")
+    }
+}
+
+/// Checks an entry of the `gateways` list of a nic
+pub fn gateways_error(gateway: &str) -> Option<&'static str> {
+    if IP_RE.is_match(gateway) || is_ip6(gateway) {
+        None
+    } else {
+        Some("Invalid gateway")
+    }
+}
+
+/// Checks every entry of a list with `check`, `field` is the path of
+/// the list in the payload.
+pub fn list_errors(
+    field: &str,
+    list: &[String],
+    check: fn(&str) -> Option<&'static str>,
+) -> Vec<ValidationError> {
+    list.iter()
+        .enumerate()
+        .filter_map(|(n, value)| {
+            check(value.as_str()).map(|error| {
+                ValidationError::new(format!("{}.{}", field, n).as_str(), error)
+            })
+        })
+        .collect()
+}
+
+/// Checks that a nic tag is one of the configured networks
+pub fn nic_tag_error(config: &Config, nic_tag: &str) -> Option<&'static str> {
+    if config.settings.networks.contains_key(nic_tag) {
+        None
+    } else {
+        Some("Unknown nic_tag")
+    }
+}
+
+/// Checks a VLAN id
+pub fn vlan_error(vlan: u16) -> Option<&'static str> {
+    if vlan < 1 || vlan > 4094 {
+        Some("VLAN id must be between 1 and 4094")
+    } else {
+        None
+    }
+}
+
+/// Checks the MTU of a nic
+pub fn mtu_error(mtu: u32) -> Option<&'static str> {
+    if mtu < 576 || mtu > 9000 {
+        Some("MTU must be between 576 and 9000")
+    } else {
+        None
+    }
+}
+
+/// Checks a devfs path pattern
+pub fn devfs_error(pattern: &str) -> Option<&'static str> {
+    if !DEVFS_RE.is_match(pattern) || pattern.starts_with('/') || pattern.contains("..") {
+        Some("Invalid device pattern")
+    } else {
+        None
+    }
+}
+
+/// rctl resources thresholds can be set for
+static RCTL_RESOURCES: &'static [&'static str] = &[
+    "cputime",
+    "datasize",
+    "stacksize",
+    "coredumpsize",
+    "memoryuse",
+    "memorylocked",
+    "maxproc",
+    "openfiles",
+    "vmemoryuse",
+    "pseudoterminals",
+    "swapuse",
+    "nthr",
+    "msgqqueued",
+    "msgqsize",
+    "nmsgq",
+    "nsem",
+    "nsemop",
+    "nshm",
+    "shmsize",
+    "wallclock",
+    "pcpu",
+    "readbps",
+    "writebps",
+    "readiops",
+    "writeiops",
+];
+
+/// rctl actions a threshold can trigger
+static RCTL_ACTIONS: &'static [&'static str] = &[
+    "deny",
+    "log",
+    "devctl",
+    "throttle",
+    "sighup",
+    "sigint",
+    "sigkill",
+    "sigsegv",
+    "sigterm",
+    "sigxcpu",
+    "sigxfsz",
+];
+
+/// A rctl rule in addition to the deny limit of a resource, a `deny`
+/// threshold replaces the limit.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Threshold {
+    /// rctl action, like `log` or `devctl`
+    pub action: String,
+    /// amount in rctl syntax (`512M`) or as percentage of the limit
+    /// of the resource (`90%`)
+    pub amount: String,
+}
+
+impl Threshold {
+    /// The amount as percentage if it is given as one
+    fn percent(&self) -> Option<u64> {
+        if self.amount.ends_with('%') {
+            self.amount[..self.amount.len() - 1].parse().ok()
+        } else {
+            None
+        }
+    }
+
+    /// checks the threshold for errors, `limited` tells if the
+    /// resource has a limit to take a percentage of.
+    fn errors(&self, field: &str, limited: bool) -> Vec<ValidationError> {
+        let mut errors = Vec::new();
+        if !RCTL_ACTIONS.contains(&self.action.as_str()) {
+            errors.push(ValidationError::new(field, "Unknown action"))
+        }
+        if self.amount.ends_with('%') {
+            match self.percent() {
+                Some(percent) if percent > 0 && percent <= 100 => {
+                    if !limited {
+                        errors.push(ValidationError::new(
+                            field,
+                            "Percentage of a resource without limit",
+                        ))
+                    }
+                }
+                _ => errors.push(ValidationError::new(field, "Invalid percentage")),
+            }
+        } else if !AMOUNT_RE.is_match(self.amount.as_str()) {
+            errors.push(ValidationError::new(field, "Invalid amount"))
+        }
+        errors
+    }
+}
+
+/// Checks thresholds per resource, `limited` are the resources that
+/// have a limit to take a percentage of, `None` if that depends on the
+/// jail (like for the defaults in the vmadm.toml).
+pub fn thresholds_errors(
+    path: &str,
+    thresholds: &Map<String, Vec<Threshold>>,
+    limited: Option<&[&str]>,
+) -> Vec<ValidationError> {
+    let mut errors = Vec::new();
+    for (resource, list) in thresholds.iter() {
+        if !RCTL_RESOURCES.contains(&resource.as_str()) {
+            errors.push(ValidationError::new(
+                format!("{}.{}", path, resource).as_str(),
+                "Unknown resource",
+            ));
+            continue;
+        }
+        let has_limit = limited.map_or(true, |limited| limited.contains(&resource.as_str()));
+        for (i, threshold) in list.iter().enumerate() {
+            let field = format!("{}.{}.{}", path, resource, i);
+            errors.append(&mut threshold.errors(field.as_str(), has_limit));
+            if list[..i].iter().any(|other| other == threshold) {
+                errors.push(ValidationError::new(field.as_str(), "Duplicate threshold"))
+            }
+        }
+    }
+    errors
+}
+
+/// Type of the value of a jail(8) parameter
+enum ParamKind {
+    Bool,
+    /// an integer within the given bounds
+    Int(i64, i64),
+    /// one of the given strings
+    Choice(&'static [&'static str]),
+}
+
+static SYSV_VALUES: &'static [&'static str] = &["disable", "inherit", "new"];
+
+/// jail(8) parameters that can be set in `jail_params`, the ones
+/// managed by vmadm itself (name, path, vnet, exec.*, ...) are not
+/// part of it.
+static JAIL_PARAMS: &'static [(&'static str, ParamKind)] = &[
+    ("securelevel", ParamKind::Int(-1, 3)),
+    ("enforce_statfs", ParamKind::Int(0, 2)),
+    ("children.max", ParamKind::Int(0, 65535)),
+    ("devfs_ruleset", ParamKind::Int(0, 65535)),
+    ("sysvmsg", ParamKind::Choice(SYSV_VALUES)),
+    ("sysvsem", ParamKind::Choice(SYSV_VALUES)),
+    ("sysvshm", ParamKind::Choice(SYSV_VALUES)),
+    ("allow.set_hostname", ParamKind::Bool),
+    ("allow.sysvipc", ParamKind::Bool),
+    ("allow.raw_sockets", ParamKind::Bool),
+    ("allow.chflags", ParamKind::Bool),
+    ("allow.mlock", ParamKind::Bool),
+    ("allow.quotas", ParamKind::Bool),
+    ("allow.socket_af", ParamKind::Bool),
+    ("allow.reserved_ports", ParamKind::Bool),
+    ("allow.read_msgbuf", ParamKind::Bool),
+    ("allow.unprivileged_proc_debug", ParamKind::Bool),
+    ("allow.mount", ParamKind::Bool),
+    ("allow.mount.devfs", ParamKind::Bool),
+    ("allow.mount.fdescfs", ParamKind::Bool),
+    ("allow.mount.nullfs", ParamKind::Bool),
+    ("allow.mount.procfs", ParamKind::Bool),
+    ("allow.mount.linprocfs", ParamKind::Bool),
+    ("allow.mount.linsysfs", ParamKind::Bool),
+    ("allow.mount.tmpfs", ParamKind::Bool),
+    ("allow.mount.zfs", ParamKind::Bool),
+];
+
+/// Value of a jail(8) parameter
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum JailParam {
+    Bool(bool),
+    Int(i64),
+    Str(String),
+}
+
+impl JailParam {
+    /// checks the value against the kind of the parameter `name`
+    fn error(&self, name: &str) -> Option<&'static str> {
+        let kind = match JAIL_PARAMS.iter().find(|&&(param, _)| param == name) {
+            Some(&(_, ref kind)) => kind,
+            None => return Some("Unknown jail parameter"),
+        };
+        match (kind, self) {
+            (&ParamKind::Bool, &JailParam::Bool(_)) => None,
+            (&ParamKind::Int(min, max), &JailParam::Int(n)) if n >= min && n <= max => None,
+            (&ParamKind::Int(_, _), &JailParam::Int(_)) => Some("Value out of range"),
+            (&ParamKind::Choice(choices), &JailParam::Str(ref value))
+                if choices.contains(&value.as_str()) => None,
+            _ => Some("Invalid value"),
+        }
+    }
+
+    /// The parameter as argument to jail(8), false booleans use the
+    /// `no` prefix (`allow.nomlock`).
+    pub fn arg(&self, name: &str) -> String {
+        match *self {
+            JailParam::Bool(true) => String::from(name),
+            JailParam::Bool(false) => {
+                match name.rfind('.') {
+                    Some(idx) => format!("{}no{}", &name[..idx + 1], &name[idx + 1..]),
+                    None => format!("no{}", name),
+                }
+            }
+            JailParam::Int(n) => format!("{}={}", name, n),
+            JailParam::Str(ref value) => format!("{}={}", name, value),
+        }
+    }
+}
+
+/// jail(8) parameters of the outer and the inner jail
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct JailParams {
+    #[serde(default = "empty_params")]
+    pub outer: Map<String, JailParam>,
+    #[serde(default = "empty_params")]
+    pub inner: Map<String, JailParam>,
+}
+
+impl JailParams {
+    pub fn new() -> Self {
+        JailParams {
+            outer: Map::new(),
+            inner: Map::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.outer.is_empty() && self.inner.is_empty()
+    }
+
+    /// The parameters vmadm uses when nothing else is configured
+    fn defaults(config: &Config) -> Self {
+        let mut outer = Map::new();
+        outer.insert(
+            String::from("devfs_ruleset"),
+            JailParam::Int(i64::from(config.settings.devfs_ruleset)),
+        );
+        // the outer jail has to allow the inner one
+        outer.insert(String::from("children.max"), JailParam::Int(1));
+        let mut inner = Map::new();
+        inner.insert(String::from("devfs_ruleset"), JailParam::Int(4));
+        for params in [&mut outer, &mut inner].iter_mut() {
+            params.insert(String::from("securelevel"), JailParam::Int(2));
+            params.insert(String::from("allow.raw_sockets"), JailParam::Bool(true));
+            for sysv in ["sysvmsg", "sysvsem", "sysvshm"].iter() {
+                params.insert(String::from(*sysv), JailParam::Str(String::from("new")));
+            }
+        }
+        JailParams { outer, inner }
+    }
+
+    /// Overwrites the parameters with the ones of `other`
+    fn merge(&mut self, other: &JailParams) {
+        for (name, value) in other.outer.iter() {
+            self.outer.insert(name.clone(), value.clone());
+        }
+        for (name, value) in other.inner.iter() {
+            self.inner.insert(name.clone(), value.clone());
+        }
+    }
+
+    /// checks the parameters against the allowed ones
+    pub fn errors(&self, path: &str) -> Vec<ValidationError> {
+        let mut errors = Vec::new();
+        for &(jail, params) in [("outer", &self.outer), ("inner", &self.inner)].iter() {
+            for (name, value) in params.iter() {
+                if let Some(error) = value.error(name.as_str()) {
+                    let field = format!("{}.{}.{}", path, jail, name);
+                    errors.push(ValidationError::new(field.as_str(), error))
+                }
+            }
+        }
+        errors
+    }
+}
+
+/// How a changed property gets applied to a running jail
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Apply {
+    /// only stored, nothing needs to be applied
+    Stored,
+    /// the quota of the jail dataset is set
+    Quota,
+    /// the rctl rules are replaced
+    Rctl,
+    /// the jails are pinned to the new cpus
+    Cpuset,
+    /// the nic is added or removed
+    Nics,
+    /// the changed routes are set
+    Routes,
+    /// the jail needs to be restarted
+    Restart,
+}
+
+/// How changes to the properties of a config are applied to a running
+/// jail, `Jail::update` applies them accordingly. Properties that are
+/// not listed need a restart.
+static APPLY: &'static [(&'static str, Apply)] = &[
+    ("alias", Apply::Stored),
+    ("autostart", Apply::Stored),
+    ("hooks", Apply::Stored),
+    ("archive_on_delete", Apply::Stored),
+    ("billing_id", Apply::Stored),
+    ("do_not_inventory", Apply::Stored),
+    ("dns_domain", Apply::Stored),
+    ("indestructible_delegated", Apply::Stored),
+    ("indestructible_zoneroot", Apply::Stored),
+    ("owner_uuid", Apply::Stored),
+    ("package_name", Apply::Stored),
+    ("package_version", Apply::Stored),
+    ("quota", Apply::Quota),
+    ("max_physical_memory", Apply::Rctl),
+    ("cpu_cap", Apply::Rctl),
+    ("max_shm_memory", Apply::Rctl),
+    ("max_locked_memory", Apply::Rctl),
+    ("max_lwps", Apply::Rctl),
+    ("thresholds", Apply::Rctl),
+    ("max_swap", Apply::Rctl),
+    ("max_vmemory", Apply::Rctl),
+    ("max_open_files", Apply::Rctl),
+    ("max_read_bps", Apply::Rctl),
+    ("max_write_bps", Apply::Rctl),
+    ("max_read_iops", Apply::Rctl),
+    ("max_write_iops", Apply::Rctl),
+    ("max_msg_ids", Apply::Rctl),
+    ("max_sem_ids", Apply::Rctl),
+    ("max_shm_ids", Apply::Rctl),
+    ("cpuset", Apply::Cpuset),
+    ("routes", Apply::Routes),
+];
+
+/// How a change to a (top level) property is applied
+fn apply_kind(field: &str) -> Apply {
+    let property = field.split('.').next().unwrap_or(field);
+    APPLY
+        .iter()
+        .find(|&&(name, _)| name == property)
+        .map_or(Apply::Restart, |&(_, apply)| apply)
+}
+
+/// A changed property between two configs
+#[derive(Debug, Serialize)]
+pub struct Change {
+    /// path of the property
+    pub field: String,
+    /// value before the change, `None` if it was added
+    pub old: Option<serde_json::Value>,
+    /// value after the change, `None` if it was removed
+    pub new: Option<serde_json::Value>,
+    /// how the change is applied to a running jail
+    pub apply: Apply,
+}
+
+impl Change {
+    /// weather the change can be applied to a running jail
+    pub fn live(&self) -> bool {
+        self.apply != Apply::Restart
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let show = |value: &Option<serde_json::Value>| match *value {
+            Some(ref value) => value.to_string(),
+            None => String::from("-"),
+        };
+        let apply = if self.live() { "live" } else { "needs restart" };
+        write!(f, "{}: {} -> {} ({})", self.field, show(&self.old), show(&self.new), apply)
+    }
+}
+
+/// The properties that differ between two JSON objects.
+fn object_changes(prefix: &str, old: &serde_json::Value, new: &serde_json::Value) -> Vec<Change> {
+    let empty = serde_json::Map::new();
+    let old = old.as_object().unwrap_or(&empty);
+    let new = new.as_object().unwrap_or(&empty);
+    let mut keys: Vec<&String> = old.keys().chain(new.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    let mut res = Vec::new();
+    for key in keys {
+        let (old_value, new_value) = (old.get(key), new.get(key));
+        if old_value != new_value {
+            let field = if prefix.is_empty() {
+                key.clone()
+            } else {
+                format!("{}.{}", prefix, key)
+            };
+            res.push(Change {
+                field: field,
+                old: old_value.cloned(),
+                new: new_value.cloned(),
+                apply: Apply::Restart,
+            })
+        }
+    }
+    res
+}
+
+/// Checks if a string is a valid IPv6 address, link local addresses
+/// may carry a `%<interface>` scope.
+pub fn is_ip6(ip: &str) -> bool {
+    let addr = match ip.find('%') {
+        Some(idx) => &ip[..idx],
+        None => ip,
+    };
+    addr.parse::<Ipv6Addr>().is_ok()
+}
+
+/// Checks if a string is a IPv4 address with a prefix length.
+pub fn is_ip4_cidr(cidr: &str) -> bool {
+    parse_ip4_cidr(cidr).is_some()
+}
+
+/// Checks if a string is a IPv6 address with a prefix length.
+pub fn is_ip6_cidr(cidr: &str) -> bool {
+    parse_ip6_cidr(cidr).is_some()
+}
+
+/// Splits an IPv4 address in CIDR notation into address and prefix.
+fn parse_ip4_cidr(cidr: &str) -> Option<(u32, u8)> {
+    let mut parts = cidr.splitn(2, '/');
+    let addr = parts.next().unwrap_or("");
+    match parts.next().map(|len| len.parse::<u8>()) {
+        Some(Ok(len)) if len <= 32 && IP_RE.is_match(addr) => {
+            addr.parse::<Ipv4Addr>().ok().map(|addr| (u32::from(addr), len))
+        }
+        _ => None,
+    }
+}
+
+/// Splits an IPv6 address in CIDR notation into address and prefix.
+fn parse_ip6_cidr(cidr: &str) -> Option<(Ipv6Addr, u8)> {
+    let mut parts = cidr.splitn(2, '/');
+    let addr = parts.next().unwrap_or("");
+    match parts.next().map(|len| len.parse::<u8>()) {
+        Some(Ok(len)) if len <= 128 => addr.parse::<Ipv6Addr>().ok().map(|addr| (addr, len)),
+        _ => None,
+    }
+}
+
+/// Checks if two IPv6 addresses share the first `prefix` bits.
+fn same_ip6_net(a: &Ipv6Addr, b: &Ipv6Addr, prefix: u8) -> bool {
+    let a = a.octets();
+    let b = b.octets();
+    let mut bits = prefix as usize;
+    for i in 0..16 {
+        if bits == 0 {
+            break;
+        }
+        let mask: u8 = if bits >= 8 { 0xff } else { !(0xffu8 >> bits) };
+        if a[i] & mask != b[i] & mask {
+            return false;
+        }
+        bits = bits.saturating_sub(8);
+    }
+    true
+}
+
+/// checks a route for errors, if `nics` is given `nics[<index>]`
+/// gateways are checked against it.
+pub fn route_errors(
+    field: &str,
+    dst: &str,
+    gateway: &str,
+    nics: Option<usize>,
+) -> Vec<ValidationError> {
+    let mut errors = Vec::new();
+    let inet6 = dst.contains(':');
+    let valid_dst = if inet6 {
+        is_ip6(dst) || is_ip6_cidr(dst)
+    } else {
+        IP_RE.is_match(dst) || is_ip4_cidr(dst)
+    };
+    if !valid_dst {
+        errors.push(ValidationError::new(field, "Invalid route destination"))
+    }
+    match (nic_ref(gateway), nics) {
+        (Some(idx), Some(nics)) if idx >= nics => {
+            errors.push(ValidationError::new(field, "Unknown nic"))
+        }
+        (Some(_), _) => (),
+        (None, _) if inet6 && is_ip6(gateway) => (),
+        (None, _) if !inet6 && IP_RE.is_match(gateway) => (),
+        (None, _) => errors.push(ValidationError::new(field, "Invalid route gateway")),
+    }
+    errors
+}
+
+/// Parses a `nics[<index>]` route target into the nic index
+pub fn nic_ref(gateway: &str) -> Option<usize> {
+    if gateway.starts_with("nics[") && gateway.ends_with(']') {
+        gateway[5..gateway.len() - 1].parse().ok()
+    } else {
+        None
+    }
+}
+
+/// Brings an address in CIDR notation into its canonical form,
+/// returns `None` if the address is not valid.
+fn normalize_cidr(cidr: &str) -> Option<String> {
+    let mut parts = cidr.trim().splitn(2, '/');
+    let addr = parts.next().unwrap_or("");
+    let len = match parts.next().map(|len| len.parse::<u8>()) {
+        Some(Ok(len)) => len,
+        _ => return None,
+    };
+    if let Ok(ip) = addr.parse::<Ipv4Addr>() {
+        if len <= 32 {
+            return Some(format!("{}/{}", ip, len));
+        }
+    } else if let Ok(ip) = addr.parse::<Ipv6Addr>() {
+        if len <= 128 {
+            return Some(format!("{}/{}", ip, len));
+        }
+    }
+    None
+}
+
+/// Translates a netmask into a prefix length, returns `None` if the
+/// netmask is invalid.
+pub fn netmask_to_prefix(netmask: &str) -> Option<u8> {
+    let mask = match netmask.parse::<Ipv4Addr>() {
+        Ok(mask) => u32::from(mask),
+        Err(_) => return None,
+    };
+    // the prefix is the number of leading ones
+    let prefix = (!mask).leading_zeros();
+    if prefix < 32 && mask << prefix != 0 {
+        // there are bits set after the first unset one
+        None
+    } else {
+        Some(prefix as u8)
+    }
+}
+
+/// Translates a prefix length into a netmask.
+fn prefix_to_netmask(prefix: u8) -> Option<String> {
+    if prefix > 32 {
+        return None;
+    }
+    Some(Ipv4Addr::from(prefix_to_mask(prefix)).to_string())
+}
+
+/// Translates a prefix length into the bitmask of the network part.
+fn prefix_to_mask(prefix: u8) -> u32 {
+    if prefix == 0 {
+        0
+    } else {
+        !0u32 << (32 - prefix.min(32))
     }
 }
 
@@ -455,6 +2143,38 @@ fn new_uuid() -> Uuid {
     Uuid::new_v4()
 }
 
+fn empty_strings() -> Vec<String> {
+    Vec::new()
+}
+
+fn empty_params() -> Map<String, JailParam> {
+    Map::new()
+}
+
+fn empty_jail_params() -> JailParams {
+    JailParams::new()
+}
+
+fn empty_thresholds() -> Map<String, Vec<Threshold>> {
+    Map::new()
+}
+
+fn empty_hooks() -> Map<String, Vec<String>> {
+    Map::new()
+}
+
+fn empty_env() -> Map<String, String> {
+    Map::new()
+}
+
+fn empty_devfs() -> Vec<String> {
+    Vec::new()
+}
+
+fn empty_routes() -> Map<String, String> {
+    Map::new()
+}
+
 fn empty_nics() -> Vec<NIC> {
     Vec::new()
 }
@@ -463,17 +2183,766 @@ fn dflt_brand() -> String {
     String::from("jail")
 }
 
-fn new_mac() -> String {
+/// Parses a mac prefix of 1 to 5 colon separated octets
+fn mac_oui(prefix: &str) -> Option<Vec<u8>> {
+    let octets: Vec<&str> = prefix.split(':').collect();
+    if octets.is_empty() || octets.len() > 5 {
+        return None;
+    }
+    octets
+        .iter()
+        .map(|octet| if octet.is_empty() || octet.len() > 2 {
+            None
+        } else {
+            u8::from_str_radix(octet, 16).ok()
+        })
+        .collect()
+}
+
+/// Checks a mac prefix, it has to be a locally administered one (the
+/// second bit of the first octet set) so generated addresses don't
+/// clash with ones assigned by a vendor.
+pub fn mac_oui_error(prefix: &str) -> Option<&'static str> {
+    match mac_oui(prefix) {
+        None => Some("Invalid mac prefix, expected 1 to 5 hex octets"),
+        Some(ref octets) if octets[0] & 0x02 == 0 => {
+            Some("The mac prefix has to be locally administered")
+        }
+        Some(_) => None,
+    }
+}
+
+/// Generates a random mac address starting with the given prefix of
+/// colon separated octets.
+pub fn new_mac(prefix: &str) -> Result<String, Box<Error>> {
+    if let Some(error) = mac_oui_error(prefix) {
+        return Err(GenericError::bx(error));
+    }
     let mut rng = thread_rng();
-    // the second half of the first ocet should be 02
-    // to indicate this is a locally administered mac
-    // address and not one assiged by a vendor.
-    format!(
-        "02:{:x}:{:x}:{:x}:{:x}:{:x}",
-        rng.gen::<u8>(),
-        rng.gen::<u8>(),
-        rng.gen::<u8>(),
-        rng.gen::<u8>(),
-        rng.gen::<u8>()
+    let mut octets = mac_oui(prefix).unwrap_or_default();
+    while octets.len() < 6 {
+        octets.push(rng.gen::<u8>());
+    }
+    Ok(
+        octets
+            .iter()
+            .map(|octet| format!("{:02x}", octet))
+            .collect::<Vec<String>>()
+            .join(":"),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use jail_config::*;
+    use config::Settings;
+
+    fn config() -> Config {
+        let mut networks = Map::new();
+        networks.insert(String::from("admin"), String::from("bridge0"));
+        Config {
+            settings: Settings {
+                pool: String::from("zroot/jails"),
+                repo: String::from("https://bsd.project-fifo.net/images"),
+                conf_dir: String::from("/etc/jails"),
+                image_dir: String::from("/var/imgadm/images"),
+                devfs_ruleset: 4,
+                devfs_ruleset_base: 1000,
+                mac_oui: String::from("02"),
+                strict: false,
+                history_size: 10,
+                thresholds: Map::new(),
+                jail_params: JailParams::new(),
+                hooks: Map::new(),
+                networks: networks,
+            },
+            req_id: String::from("test"),
+        }
+    }
+
+    fn nic(interface: &str, mac: &str, ip: &str) -> NIC {
+        NIC {
+            interface: String::from(interface),
+            mac: String::from(mac),
+            vlan: None,
+            nic_tag: String::from("admin"),
+            ip: String::from(ip),
+            netmask: Some(String::from("255.255.255.0")),
+            gateway: Some(String::from("192.168.254.1")),
+            ip6: None,
+            gateway6: None,
+            ips: vec![],
+            gateways: vec![],
+            primary: false,
+            mtu: None,
+            network_uuid: None,
+        }
+    }
+
+    fn conf() -> JailConfig {
+        let json = r#"{
+            "image_uuid": "616d4ab2-832c-11e7-9392-784f438c8d54",
+            "alias": "test",
+            "hostname": "test",
+            "max_physical_memory": 1024,
+            "cpu_cap": 100,
+            "quota": 5
+        }"#;
+        let mut conf = JailConfig::from_reader(&config(), json.as_bytes()).unwrap();
+        let mut net0 = nic("net0", "02:00:00:00:00:01", "192.168.254.10");
+        net0.primary = true;
+        conf.nics = vec![net0, nic("net1", "02:00:00:00:00:02", "192.168.254.11")];
+        conf
+    }
+
+    fn fields(conf: &JailConfig) -> Vec<String> {
+        match conf.errors(&config()) {
+            Some(errors) => errors.iter().map(|e| String::from(e.field())).collect(),
+            None => vec![],
+        }
+    }
+
+    #[test]
+    fn boot_command() {
+        let json = r#"{
+            "image_uuid": "616d4ab2-832c-11e7-9392-784f438c8d54",
+            "alias": "test",
+            "hostname": "test",
+            "max_physical_memory": 1024,
+            "cpu_cap": 100,
+            "quota": 5,
+            "boot_command": "/usr/local/bin/app"
+        }"#;
+        let conf = JailConfig::from_reader(&config(), json.as_bytes()).unwrap();
+        assert_eq!(Some(String::from("/usr/local/bin/app")), conf.init_name);
+        let json = json.replace("\"quota\"", "\"init_name\": \"/bin/sh\", \"quota\"");
+        assert!(JailConfig::from_reader(&config(), json.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn stored_configs_are_not_revalidated() {
+        let mut conf = conf();
+        conf.nics[0].gateway = Some(String::from("10.0.0.1"));
+        conf.nics[0].gateways = vec![];
+        let mut path = std::env::temp_dir();
+        path.push(format!("{}.json", conf.uuid.hyphenated()));
+        // stored configs may contain properties that are no longer known
+        let mut value = serde_json::to_value(&conf).unwrap();
+        value.as_object_mut().unwrap().insert(
+            String::from("zonename"),
+            serde_json::Value::String(String::from("test")),
+        );
+        serde_json::to_writer(File::create(&path).unwrap(), &value).unwrap();
+        let stored = JailConfig::from_file(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(Some(String::from("10.0.0.1")), stored.unwrap().nics[0].gateway);
+    }
+
+    #[test]
+    fn valid() {
+        assert_eq!(Vec::<String>::new(), fields(&conf()));
+    }
+    #[test]
+    fn non_contiguous_netmask() {
+        let mut conf = conf();
+        conf.nics[1].netmask = Some(String::from("255.0.255.0"));
+        assert_eq!(vec!["nics.1.netmask"], fields(&conf));
+    }
+    #[test]
+    fn gateway_outside_subnet() {
+        let mut conf = conf();
+        conf.nics[1].gateway = Some(String::from("10.0.0.1"));
+        assert_eq!(vec!["nics.1.gateway"], fields(&conf));
+    }
+    #[test]
+    fn link_local_gateway6() {
+        let mut conf = conf();
+        conf.nics[0].ip6 = Some(String::from("fd00::10/64"));
+        conf.nics[0].gateway6 = Some(String::from("fe80::1"));
+        assert_eq!(Vec::<String>::new(), fields(&conf));
+        conf.nics[0].gateway6 = Some(String::from("fd01::1"));
+        assert_eq!(vec!["nics.0.gateway6"], fields(&conf));
+    }
+    #[test]
+    fn primary_without_gateway() {
+        let mut conf = conf();
+        conf.nics[1].gateway = None;
+        assert_eq!(Vec::<String>::new(), fields(&conf));
+        conf.nics[0].gateway = None;
+        assert_eq!(vec!["nics.0.gateway"], fields(&conf));
+        conf.nics[0].ip = String::from("dhcp");
+        assert_eq!(Vec::<String>::new(), fields(&conf));
+    }
+    #[test]
+    fn dhcp_unhides_bpf() {
+        let mut conf = conf();
+        assert!(conf.devfs_patterns().is_empty());
+        conf.nics[0].ip = String::from("dhcp");
+        assert_eq!(vec!["bpf*"], conf.devfs_patterns());
+        conf.devfs = vec![String::from("bpf*")];
+        assert_eq!(vec!["bpf*"], conf.devfs_patterns());
+    }
+    #[test]
+    fn duplicate_interface() {
+        let mut conf = conf();
+        conf.nics[1].interface = String::from("net0");
+        assert_eq!(vec!["nics.1.interface"], fields(&conf));
+    }
+    #[test]
+    fn invalid_interface() {
+        let mut conf = conf();
+        conf.nics[1].interface = String::from("lo1");
+        assert_eq!(vec!["nics.1.interface"], fields(&conf));
+        conf.nics[1].interface = String::from("net");
+        assert_eq!(vec!["nics.1.interface"], fields(&conf));
+    }
+    #[test]
+    fn duplicate_mac() {
+        let mut conf = conf();
+        conf.nics[1].mac = String::from("02:00:00:00:00:01");
+        assert_eq!(vec!["nics.1.mac"], fields(&conf));
+    }
+    #[test]
+    fn multiple_primary() {
+        let mut conf = conf();
+        conf.nics[1].primary = true;
+        assert_eq!(vec!["nics.1.primary"], fields(&conf));
+    }
+    #[test]
+    fn vlan_range() {
+        let mut conf = conf();
+        conf.nics[0].vlan = Some(0);
+        conf.nics[1].vlan = Some(4095);
+        assert_eq!(vec!["nics.0.vlan", "nics.1.vlan"], fields(&conf));
+        conf.nics[0].vlan = Some(1);
+        conf.nics[1].vlan = Some(4094);
+        assert_eq!(Vec::<String>::new(), fields(&conf));
+    }
+    #[test]
+    fn mtu_range() {
+        let mut conf = conf();
+        conf.nics[0].mtu = Some(100);
+        conf.nics[1].mtu = Some(65000);
+        assert_eq!(vec!["nics.0.mtu", "nics.1.mtu"], fields(&conf));
+    }
+    #[test]
+    fn start_script() {
+        let mut nic = nic("net0", "02:00:00:00:00:01", "192.168.254.10");
+        nic.primary = true;
+        assert_eq!(
+            "/sbin/ifconfig epair0b name net0; \
+             /sbin/ifconfig net0 ether 02:00:00:00:00:01; \
+             /sbin/ifconfig net0 inet 192.168.254.10/24; \
+             /sbin/route add default -gateway 192.168.254.1; ",
+            nic.start_script("epair0")
+        );
+    }
+    #[test]
+    fn start_script_mtu() {
+        let mut nic = nic("net1", "02:00:00:00:00:02", "192.168.254.11");
+        nic.mtu = Some(9000);
+        assert_eq!(
+            "/sbin/ifconfig epair1b name net1; \
+             /sbin/ifconfig net1 ether 02:00:00:00:00:02 mtu 9000; \
+             /sbin/ifconfig net1 inet 192.168.254.11/24; ",
+            nic.start_script("epair1")
+        );
+    }
+    #[test]
+    fn start_script_vlan() {
+        let mut nic = nic("net0", "02:00:00:00:00:01", "192.168.254.10");
+        nic.vlan = Some(42);
+        nic.mtu = Some(1400);
+        assert_eq!(
+            "/sbin/ifconfig epair0b name net0p; \
+             /sbin/ifconfig net0p mtu 1400; \
+             /sbin/ifconfig net0p.42 create vlan 42 vlandev net0p; \
+             /sbin/ifconfig net0p.42 name net0; \
+             /sbin/ifconfig net0 ether 02:00:00:00:00:01 mtu 1400; \
+             /sbin/ifconfig net0 inet 192.168.254.10/24; ",
+            nic.start_script("epair0")
+        );
+    }
+    /// A primary nic with only IPv6 configured
+    fn nic6(ip6: &str, gateway6: Option<&str>) -> NIC {
+        let mut nic = nic("net0", "02:00:00:00:00:01", "");
+        nic.netmask = None;
+        nic.gateway = None;
+        nic.primary = true;
+        nic.ip6 = Some(String::from(ip6));
+        nic.gateway6 = gateway6.map(String::from);
+        nic
+    }
+    #[test]
+    fn start_script_ip6() {
+        let nic = nic6("fd00::10/64", Some("fd00::1"));
+        assert_eq!(
+            "/sbin/ifconfig epair0b name net0; \
+             /sbin/ifconfig net0 ether 02:00:00:00:00:01; \
+             /sbin/ifconfig net0 inet6 -ifdisabled fd00::10/64; \
+             /sbin/route -6 add default fd00::1; ",
+            nic.start_script("epair0")
+        );
+    }
+    #[test]
+    fn addr_script_addrconf() {
+        let nic = nic6("addrconf", Some("fe80::1"));
+        assert_eq!(
+            "/sbin/ifconfig net0 inet6 -ifdisabled accept_rtadv auto_linklocal; \
+             /sbin/rtsol net0; \
+             /sbin/route -6 add default fe80::1%net0; ",
+            nic.addr_script()
+        );
+    }
+    #[test]
+    fn addr_script_dual_stack() {
+        let mut nic = nic("net0", "02:00:00:00:00:01", "192.168.254.10");
+        nic.primary = true;
+        nic.ip6 = Some(String::from("fd00::10/64"));
+        nic.gateway6 = Some(String::from("fd00::1"));
+        assert_eq!(
+            "/sbin/ifconfig net0 inet 192.168.254.10/24; \
+             /sbin/ifconfig net0 inet6 -ifdisabled fd00::10/64; \
+             /sbin/route add default -gateway 192.168.254.1; \
+             /sbin/route -6 add default fd00::1; ",
+            nic.addr_script()
+        );
+    }
+    /// A primary nic configured through the `ips` and `gateways` lists
+    fn nic_ips(ips: &[&str], gateways: &[&str]) -> NIC {
+        let mut nic = nic("net0", "02:00:00:00:00:01", "");
+        nic.netmask = None;
+        nic.gateway = None;
+        nic.primary = true;
+        nic.ips = ips.iter().map(|ip| String::from(*ip)).collect();
+        nic.gateways = gateways.iter().map(|gw| String::from(*gw)).collect();
+        nic.normalize();
+        nic
+    }
+    #[test]
+    fn addr_script_ips() {
+        let nic = nic_ips(&["192.168.254.10/24", "fd00::10/64"], &["192.168.254.1", "fe80::1"]);
+        assert_eq!(
+            "/sbin/ifconfig net0 inet 192.168.254.10/24; \
+             /sbin/ifconfig net0 inet6 -ifdisabled fd00::10/64; \
+             /sbin/route add default -gateway 192.168.254.1; \
+             /sbin/route -6 add default fe80::1%net0; ",
+            nic.addr_script()
+        );
+    }
+    #[test]
+    fn addr_script_multiple_ips() {
+        let nic = nic_ips(
+            &["192.168.254.10/24", "192.168.254.20/24", "fd00::10/64", "fd00::20/64"],
+            &["192.168.254.1"],
+        );
+        assert_eq!(
+            "/sbin/ifconfig epair0b name net0; \
+             /sbin/ifconfig net0 ether 02:00:00:00:00:01; \
+             /sbin/ifconfig net0 inet 192.168.254.10/24; \
+             /sbin/ifconfig net0 inet 192.168.254.20/24 alias; \
+             /sbin/ifconfig net0 inet6 -ifdisabled fd00::10/64; \
+             /sbin/ifconfig net0 inet6 -ifdisabled fd00::20/64 alias; \
+             /sbin/route add default -gateway 192.168.254.1; ",
+            nic.start_script("epair0")
+        );
+    }
+    #[test]
+    fn mac_prefix() {
+        let mac = new_mac("02:08:20").unwrap();
+        assert!(mac.starts_with("02:08:20:"));
+        assert_eq!(17, mac.len());
+        assert!(MAC_RE.is_match(mac.as_str()));
+        let mac = new_mac("02").unwrap();
+        assert!(mac.starts_with("02:"));
+        assert_eq!(17, mac.len());
+    }
+    #[test]
+    fn invalid_mac_prefix() {
+        assert!(new_mac("02:zz").is_err());
+        assert!(new_mac("02::08").is_err());
+        assert!(new_mac("02:00:00:00:00:00").is_err());
+        assert!(new_mac("00:08:20").is_err());
+        assert!(mac_oui_error("0a:08").is_none());
+        let mut config = config();
+        config.settings.mac_oui = String::from("00:08:20");
+        let fields: Vec<String> = config.settings.errors().iter().map(|e| String::from(e.field())).collect();
+        assert_eq!(vec!["mac_oui"], fields);
+    }
+    #[test]
+    fn unknown_nic_key() {
+        let json = r#"{
+            "image_uuid": "616d4ab2-832c-11e7-9392-784f438c8d54",
+            "alias": "test",
+            "hostname": "test",
+            "max_physical_memory": 1024,
+            "cpu_cap": 100,
+            "quota": 5,
+            "nics": [{
+                "interface": "net0",
+                "nic_tag": "admin",
+                "ip": "dhcp",
+                "primary": true,
+                "gatway": "192.168.254.1"
+            }]
+        }"#;
+        let value: serde_json::Value = serde_json::from_str(json).unwrap();
+        let errors: Vec<String> = unknown_nic_keys(&value, "nics")
+            .iter()
+            .map(|e| String::from(e.field()))
+            .collect();
+        assert_eq!(vec!["nics.0.gatway"], errors);
+        assert!(JailConfig::from_reader(&config(), json.as_bytes()).is_err());
+    }
+    #[test]
+    fn unknown_key() {
+        let json = r#"{
+            "image_uuid": "616d4ab2-832c-11e7-9392-784f438c8d54",
+            "alias": "test",
+            "hostname": "test",
+            "max_physical_memory": 1024,
+            "max_physcal_memory": 1024,
+            "zfs_io_priority": 100,
+            "cpu_cap": 100,
+            "quota": 5
+        }"#;
+        let value: serde_json::Value = serde_json::from_str(json).unwrap();
+        let errors: Vec<String> = unknown_keys(&value, CONFIG_KEYS, "")
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(
+            vec![
+                "max_physcal_memory: Unknown property",
+                "zfs_io_priority: Unsupported property",
+            ],
+            errors
+        );
+        assert!(JailConfig::from_reader(&config(), json.as_bytes()).is_ok());
+        let mut strict = config();
+        strict.settings.strict = true;
+        assert!(JailConfig::from_reader(&strict, json.as_bytes()).is_err());
+    }
+    #[test]
+    fn config_keys() {
+        let value = serde_json::to_value(&conf()).unwrap();
+        assert!(unknown_keys(&value, CONFIG_KEYS, "").is_empty());
+    }
+    #[test]
+    fn reboot_changes() {
+        let old = conf();
+        let mut conf = conf();
+        conf.uuid = old.uuid.clone();
+        conf.max_physical_memory = 2048;
+        conf.nics.remove(1);
+        conf.nics.push(nic("net2", "02:00:00:00:00:03", "192.168.254.12"));
+        assert!(conf.reboot_changes(&old).is_empty());
+        conf.hostname = String::from("other");
+        conf.nics[0].mtu = Some(1500);
+        assert_eq!(
+            vec![
+                String::from("hostname"),
+                String::from("nics.02:00:00:00:00:01.mtu"),
+            ],
+            conf.reboot_changes(&old)
+        );
+    }
+    #[test]
+    fn diff() {
+        let old = conf();
+        let mut conf = conf();
+        conf.uuid = old.uuid.clone();
+        conf.max_physical_memory = 2048;
+        conf.nics.remove(1);
+        conf.nics.push(nic("net2", "02:00:00:00:00:03", "192.168.254.12"));
+        let changes: Vec<String> = conf.diff(&old).iter().map(|c| c.to_string()).collect();
+        assert_eq!(3, changes.len());
+        assert_eq!("max_physical_memory: 1024 -> 2048 (live)", changes[0]);
+        assert!(changes[1].starts_with("nics.02:00:00:00:00:02: {"));
+        assert!(changes[1].ends_with("} -> - (live)"));
+        assert!(changes[2].starts_with("nics.02:00:00:00:00:03: - -> {"));
+    }
+    #[test]
+    fn unapplied_changes_need_restart() {
+        let old = conf();
+        let mut conf = conf();
+        conf.uuid = old.uuid.clone();
+        conf.alias = String::from("other");
+        conf.stop_command = Some(String::from("pkill app"));
+        conf.nics[1].mtu = Some(1400);
+        assert_eq!(
+            vec![
+                String::from("stop_command"),
+                String::from("nics.02:00:00:00:00:02.mtu"),
+            ],
+            conf.reboot_changes(&old)
+        );
+    }
+    #[test]
+    fn optional_limits() {
+        let mut conf = conf();
+        conf.max_swap = Some(2048);
+        conf.max_open_files = Some(1024);
+        conf.max_read_iops = Some(500);
+        let base = format!("jail:{}", conf.uuid.hyphenated());
+        let limits = conf.rctl_limits(&config());
+        assert!(limits.contains(&format!("{}:swapuse:deny=2048M", base)));
+        assert!(limits.contains(&format!("{}:openfiles:deny=1024", base)));
+        assert!(limits.contains(&format!("{}:readiops:deny=500", base)));
+        assert!(!limits.iter().any(|limit| limit.contains("writebps")));
+    }
+    #[test]
+    fn limit_ranges() {
+        let mut conf = conf();
+        conf.max_swap = Some(512);
+        conf.max_sem_ids = Some(0);
+        assert_eq!(vec!["max_sem_ids", "max_swap"], fields(&conf));
+        conf.max_sem_ids = None;
+        conf.max_swap = Some(4096);
+        conf.max_vmemory = Some(8 * 1024 * 1024 * 1024);
+        assert_eq!(vec!["max_vmemory"], fields(&conf));
+    }
+    #[test]
+    fn thresholds() {
+        let mut config = config();
+        config.settings.thresholds.insert(
+            String::from("memoryuse"),
+            vec![Threshold { action: String::from("log"), amount: String::from("90%") }],
+        );
+        config.settings.thresholds.insert(
+            String::from("maxproc"),
+            vec![Threshold { action: String::from("devctl"), amount: String::from("100") }],
+        );
+        let mut conf = conf();
+        conf.thresholds.insert(
+            String::from("maxproc"),
+            vec![Threshold { action: String::from("log"), amount: String::from("50%") }],
+        );
+        let base = format!("jail:{}", conf.uuid.hyphenated());
+        let limits = conf.rctl_limits(&config);
+        assert!(limits.contains(&format!("{}:memoryuse:deny=1024M", base)));
+        assert!(limits.contains(&format!("{}:memoryuse:log=921M", base)));
+        assert!(limits.contains(&format!("{}:maxproc:log=1000", base)));
+        assert!(!limits.iter().any(|limit| limit.contains("devctl")));
+        conf.thresholds.insert(
+            String::from("memoryuse"),
+            vec![Threshold { action: String::from("deny"), amount: String::from("50%") }],
+        );
+        let limits = conf.rctl_limits(&config);
+        assert!(limits.contains(&format!("{}:memoryuse:deny=512M", base)));
+        assert!(!limits.contains(&format!("{}:memoryuse:deny=1024M", base)));
+    }
+    #[test]
+    fn threshold_errors() {
+        let mut conf = conf();
+        conf.thresholds.insert(
+            String::from("memoryuse"),
+            vec![
+                Threshold { action: String::from("log"), amount: String::from("90%") },
+                Threshold { action: String::from("log"), amount: String::from("90%") },
+                Threshold { action: String::from("shout"), amount: String::from("1G") },
+                Threshold { action: String::from("log"), amount: String::from("95%") },
+            ],
+        );
+        conf.thresholds.insert(
+            String::from("swapuse"),
+            vec![Threshold { action: String::from("log"), amount: String::from("90%") }],
+        );
+        conf.thresholds.insert(
+            String::from("memory"),
+            vec![Threshold { action: String::from("log"), amount: String::from("lots") }],
+        );
+        assert_eq!(
+            vec![
+                "thresholds.memory",
+                "thresholds.memoryuse.1",
+                "thresholds.memoryuse.2",
+                "thresholds.swapuse.0",
+            ],
+            fields(&conf)
+        );
+    }
+    #[test]
+    fn settings_devfs_ruleset_base() {
+        let mut config = config();
+        assert!(config.settings.errors().is_empty());
+        config.settings.devfs_ruleset_base = 4;
+        let fields: Vec<String> = config.settings.errors().iter().map(|e| String::from(e.field())).collect();
+        assert_eq!(vec!["devfs_ruleset_base"], fields);
+        config.settings.devfs_ruleset = 2000;
+        config.settings.devfs_ruleset_base = 1000;
+        assert_eq!(1, config.settings.errors().len());
+    }
+    #[test]
+    fn settings_thresholds() {
+        let mut config = config();
+        config.settings.thresholds.insert(
+            String::from("swapuse"),
+            vec![
+                Threshold { action: String::from("log"), amount: String::from("90%") },
+                Threshold { action: String::from("log"), amount: String::from("90%") },
+            ],
+        );
+        let fields: Vec<String> = config.settings.errors().iter().map(|e| String::from(e.field())).collect();
+        assert_eq!(vec!["thresholds.swapuse.1"], fields);
+    }
+    #[test]
+    fn settings_jail_params() {
+        let mut config = config();
+        config.settings.jail_params.inner.insert(String::from("securelevel"), JailParam::Int(7));
+        config.settings.jail_params.outer.insert(String::from("children.max"), JailParam::Int(0));
+        config.settings.jail_params.outer.insert(String::from("vnet"), JailParam::Bool(true));
+        let fields: Vec<String> = config.settings.errors().iter().map(|e| String::from(e.field())).collect();
+        assert_eq!(
+            vec![
+                "jail_params.outer.vnet",
+                "jail_params.inner.securelevel",
+                "jail_params.outer.children.max",
+            ],
+            fields
+        );
+    }
+    #[test]
+    fn rctl_limits() {
+        let mut conf = conf();
+        let base = format!("jail:{}", conf.uuid.hyphenated());
+        let rule = |rule: &str| format!("{}:{}", base, rule);
+        assert_eq!(
+            vec![
+                String::from("-a"),
+                rule("memoryuse:deny=1024M"),
+                rule("memorylocked:deny=1024M"),
+                rule("shmsize:deny=1024M"),
+                rule("maxproc:deny=2000"),
+                rule("pcpu:deny=100"),
+            ],
+            conf.rctl_limits(&config())
+        );
+        conf.cpu_cap = 0;
+        conf.max_shm_memory = Some(256);
+        conf.max_locked_memory = Some(128);
+        assert_eq!(
+            vec![
+                String::from("-a"),
+                rule("memoryuse:deny=1024M"),
+                rule("memorylocked:deny=128M"),
+                rule("shmsize:deny=256M"),
+                rule("maxproc:deny=2000"),
+            ],
+            conf.rctl_limits(&config())
+        );
+    }
+    #[test]
+    fn memory_defaults_round_trip() {
+        let mut conf = conf();
+        conf.normalize();
+        assert_eq!(None, conf.max_shm_memory);
+        assert_eq!(None, conf.max_locked_memory);
+        let json = serde_json::to_string(&conf).unwrap();
+        let read = JailConfig::from_reader(&config(), json.as_bytes()).unwrap();
+        assert_eq!(conf, read);
+    }
+    #[test]
+    fn cpuset() {
+        assert_eq!(Some(vec![(0, 3), (6, 6)]), parse_cpuset("0-3,6"));
+        assert_eq!(None, parse_cpuset("3-1"));
+        assert_eq!(None, parse_cpuset("0,,1"));
+        assert_eq!(None, cpuset_error("0-3", Some(4)));
+        assert_eq!(Some("CPU not present on the host"), cpuset_error("2-4", Some(4)));
+        assert_eq!(Some("Invalid cpuset"), cpuset_error("all", None));
+        assert_eq!(Some("Invalid cpuset"), cpuset_error("0-18446744073709551616", None));
+        assert_eq!(Some("CPU ids must be below 1024"), cpuset_error("0-18446744073709551615", None));
+        assert_eq!(Some("CPU not present on the host"), cpuset_error("0-4000000000", Some(8)));
+        let mut value: serde_json::Value = serde_json::from_str(r#"{"cpuset": [0, 1, 6]}"#).unwrap();
+        assert!(cpuset_from_ids(&mut value).is_empty());
+        assert_eq!("0,1,6", value["cpuset"]);
+        let mut value: serde_json::Value = serde_json::from_str(r#"{"cpuset": ["a"]}"#).unwrap();
+        assert_eq!(1, cpuset_from_ids(&mut value).len());
+    }
+
+    #[test]
+    fn jail_params() {
+        let mut config = config();
+        config.settings.jail_params.outer.insert(
+            String::from("securelevel"),
+            JailParam::Int(1),
+        );
+        let mut conf = conf();
+        conf.jail_params.outer.insert(
+            String::from("allow.mlock"),
+            JailParam::Bool(false),
+        );
+        conf.jail_params.inner.insert(
+            String::from("allow.raw_sockets"),
+            JailParam::Bool(false),
+        );
+        let params = conf.jail_params(&config);
+        assert_eq!(Some(&JailParam::Int(1)), params.outer.get("securelevel"));
+        assert_eq!(Some(&JailParam::Int(2)), params.inner.get("securelevel"));
+        assert_eq!("allow.nomlock", params.outer["allow.mlock"].arg("allow.mlock"));
+        assert_eq!("sysvshm=new", params.inner["sysvshm"].arg("sysvshm"));
+        assert!(conf.errors(&config).is_none());
+
+        conf.jail_params.outer.insert(String::from("path"), JailParam::Str(String::from("/")));
+        conf.jail_params.outer.insert(String::from("children.max"), JailParam::Int(0));
+        conf.jail_params.inner.insert(
+            String::from("sysvmsg"),
+            JailParam::Str(String::from("old")),
+        );
+        let fields: Vec<String> = conf.errors(&config)
+            .unwrap()
+            .iter()
+            .map(|e| String::from(e.field()))
+            .collect();
+        assert_eq!(
+            vec![
+                "jail_params.outer.path",
+                "jail_params.inner.sysvmsg",
+                "jail_params.outer.children.max",
+            ],
+            fields
+        );
+    }
+
+    #[test]
+    fn devfs() {
+        let config = config();
+        let mut conf = conf();
+        conf.devfs = vec![String::from("bpf*"), String::from("tun[0-9]")];
+        let rules: Vec<String> = conf.devfs_rules(&config)
+            .iter()
+            .map(|rule| rule.join(" "))
+            .collect();
+        assert_eq!(
+            vec!["add include 4", "add path bpf* unhide", "add path tun[0-9] unhide"],
+            rules
+        );
+        assert!(conf.errors(&config).is_none());
+        assert_eq!(Some("Invalid device pattern"), devfs_error("/dev/bpf"));
+        assert_eq!(Some("Invalid device pattern"), devfs_error("../mem"));
+        assert_eq!(Some("Invalid device pattern"), devfs_error("bpf; reboot"));
+    }
+
+    #[test]
+    fn exec_commands() {
+        let config = config();
+        let mut conf = conf();
+        assert_eq!("sh /etc/rc", conf.exec_start());
+        assert_eq!(None, conf.exec_stop());
+        conf.init_name = Some(String::from("/usr/local/bin/app"));
+        conf.stop_command = Some(String::from("pkill app"));
+        conf.exec_env.insert(String::from("APP_ENV"), String::from("it's prod"));
+        assert_eq!(r#"export APP_ENV='it'\''s prod'; /usr/local/bin/app"#, conf.exec_start());
+        assert_eq!(r#"export APP_ENV='it'\''s prod'; pkill app"#, conf.exec_stop().unwrap());
+        assert_eq!(r#"'sh -c '\''echo hi'\'''"#, shell_quote("sh -c 'echo hi'"));
+        assert!(conf.errors(&config).is_none());
+        conf.init_name = Some(String::from(" "));
+        conf.exec_env.insert(String::from("1BAD"), String::from("x"));
+        let fields: Vec<String> = conf.errors(&config)
+            .unwrap()
+            .iter()
+            .map(|e| String::from(e.field()))
+            .collect();
+        assert_eq!(vec!["init_name", "exec_env.1BAD"], fields);
+    }
+}
@@ -3,12 +3,18 @@
 use std::error::Error;
 use errors::GenericError;
 use std::collections::HashMap;
+use std::collections::BTreeMap as Map;
 use std::process::Command;
-use jail_config::IFace;
-use config::Config;
+use jail_config::{IFace, NIC};
+use config::{Config, DEVFS_RESERVED};
 use uuid::Uuid;
 use jdb::IdxEntry;
-use jail_config::JailConfig;
+use jail_config::{JailConfig, JailParam, Apply, HOST_CPUS};
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use zfs;
+use hooks;
 
 #[derive(Debug)]
 /// Basic information about a ZFS dataset
@@ -31,7 +37,13 @@ static MOUNT: &'static str = "mount";
 #[cfg(target_os = "freebsd")]
 static RCTL: &'static str = "rctl";
 #[cfg(target_os = "freebsd")]
+static DEVFS: &'static str = "devfs";
+#[cfg(target_os = "freebsd")]
+static CPUSET: &'static str = "cpuset";
+#[cfg(target_os = "freebsd")]
 static JAIL: &'static str = "jail";
+#[cfg(target_os = "freebsd")]
+static JEXEC: &'static str = "jexec";
 #[cfg(not(target_os = "freebsd"))]
 static MOUNT: &'static str = "echo";
 #[cfg(not(target_os = "freebsd"))]
@@ -39,7 +51,13 @@ static UMOUNT: &'static str = "echo";
 #[cfg(not(target_os = "freebsd"))]
 static RCTL: &'static str = "echo";
 #[cfg(not(target_os = "freebsd"))]
+static DEVFS: &'static str = "echo";
+#[cfg(not(target_os = "freebsd"))]
+static CPUSET: &'static str = "echo";
+#[cfg(not(target_os = "freebsd"))]
 static JAIL: &'static str = "echo";
+#[cfg(not(target_os = "freebsd"))]
+static JEXEC: &'static str = "echo";
 
 #[cfg(target_os = "freebsd")]
 static IFCONFIG: &'static str = "/sbin/ifconfig";
@@ -62,34 +80,35 @@ pub struct Jail<'a> {
 impl<'a> Jail<'a> {
     /// starts a jail
     pub fn start(&self, config: &Config) -> Result<i32, Box<Error>> {
-        self.set_rctl()?;
-        self.mount_devfs()?;
+        hooks::run(config, "pre-start", &self.config)?;
+        self.set_rctl(config)?;
+        self.mount_devfs(config)?;
         let CreateArgs { args, ifs } = create_args(config, self)?;
         debug!("Start jail"; "vm" => self.idx.uuid.hyphenated().to_string(), "args" => args.clone().join(" "));
         let id = start_jail(&self.idx.uuid, args)?;
-        let id_str = id.to_string();
-        let mut jprefix = String::from("j");
-        jprefix.push_str(id_str.as_str());
-        jprefix.push(':');
         for iface in ifs.iter() {
-            let mut epair = String::from(iface.epair.clone());
-            epair.push('a');
-            let mut target_name = jprefix.clone();
-            target_name.push_str(iface.iface.as_str());
-            let args = vec![epair, String::from("name"), target_name];
-            debug!("renaiming epair"; "vm" => self.idx.uuid.hyphenated().to_string(), "args" => args.clone().join(" "));
-            let output = Command::new(IFCONFIG).args(args.clone()).output().expect(
-                "ifconfig failed",
-            );
-            if !output.status.success() {
-                crit!("failed to rename interface"; "vm" => self.idx.uuid.hyphenated().to_string());
-            }
+            let _ = self.rename_epair(id, iface);
         }
-        Ok(0)
+        if self.config.cpuset.is_some() {
+            // the inner jail is created by the outer jails exec.start
+            let mut inner_name = self.idx.uuid.hyphenated().to_string();
+            inner_name.push('.');
+            inner_name.push_str(self.idx.uuid.hyphenated().to_string().as_str());
+            let inner = list()?.get(&inner_name).map(|inner| inner.id);
+            self.set_cpuset(id, inner)?;
+        }
+        hooks::run(config, "post-start", &self.config)
     }
 
     /// stops a jail
-    pub fn stop(&self) -> Result<i32, Box<Error>> {
+    pub fn stop(&self, config: &Config) -> Result<i32, Box<Error>> {
+        hooks::run(config, "pre-stop", &self.config)?;
+        if self.inner.is_some() && self.config.exec_stop().is_some() {
+            // removing the outer jail takes the inner one with it
+            if let Err(e) = self.stop_inner() {
+                warn!("Failed to run stop command: {}", e; "vm" => self.idx.uuid.hyphenated().to_string());
+            }
+        }
         debug!("Dleting jail"; "vm" => self.idx.uuid.hyphenated().to_string());
         let output = Command::new(JAIL)
             .args(&["-r", self.idx.uuid.hyphenated().to_string().as_str()])
@@ -127,23 +146,14 @@ impl<'a> Jail<'a> {
         }
 
         let _ = self.remove_rctl();
+        // without devfs patterns the jail uses the system rulesets
+        if !self.config.devfs_patterns().is_empty() {
+            let _ = self.remove_devfs(config);
+        }
         match self.outer {
             Some(outer) => {
-                let id_str = outer.id.to_string();
-                let mut jprefix = String::from("j");
-                jprefix.push_str(id_str.as_str());
-                jprefix.push(':');
-                for nic in self.config.nics.clone() {
-                    let mut target_name = jprefix.clone();
-                    target_name.push_str(nic.interface.as_str());
-                    let args = vec![target_name, String::from("destroy")];
-                    debug!("renaiming epair"; "vm" => self.idx.uuid.hyphenated().to_string(), "args" => args.clone().join(" "));
-                    let output = Command::new(IFCONFIG).args(args.clone()).output().expect(
-                        "ifconfig failed",
-                    );
-                    if !output.status.success() {
-                        crit!("failed to rename interface"; "vm" => self.idx.uuid.hyphenated().to_string());
-                    }
+                for nic in self.config.nics.iter() {
+                    let _ = self.remove_nic(outer, nic);
                 }
             }
             None => {
@@ -151,11 +161,300 @@ impl<'a> Jail<'a> {
             }
         }
 
+        hooks::run(config, "post-stop", &self.config)
+    }
+
+    /// Applies the changes from the old to the current configuration,
+    /// returns the properties that only take effect after a restart.
+    pub fn update(&self, config: &Config, old: &JailConfig) -> Result<Vec<String>, Box<Error>> {
+        let changes = self.config.diff(old);
+        let changed = |apply: Apply| changes.iter().any(|change| change.apply == apply);
+        if changed(Apply::Quota) {
+            self.set_quota()?;
+        }
+        let outer = match self.outer {
+            Some(outer) => outer,
+            None => return Ok(Vec::new()),
+        };
+        if changed(Apply::Rctl) {
+            // removes all rules of the jail, including every threshold
+            let _ = self.remove_rctl();
+            self.set_rctl(config)?;
+        }
+        if changed(Apply::Cpuset) {
+            self.set_cpuset(outer.id, self.inner.map(|inner| inner.id))?;
+        }
+        if changed(Apply::Nics) {
+            for nic in old.nics.iter() {
+                if !self.config.nics.iter().any(|n| n.mac == nic.mac) {
+                    self.remove_nic(outer, nic)?;
+                }
+            }
+            for nic in self.config.nics.iter() {
+                if !old.nics.iter().any(|n| n.mac == nic.mac) {
+                    self.add_nic(config, outer, nic)?;
+                }
+            }
+        }
+        if changed(Apply::Routes) {
+            self.update_routes(old)?;
+        }
+        Ok(changes.into_iter().filter(|change| !change.live()).map(|change| change.field).collect())
+    }
+
+    /// Pins the outer and inner jail to the cpus of the config, without
+    /// a cpuset they are allowed to use all cpus again.
+    fn set_cpuset(&self, outer: u64, inner: Option<u64>) -> Result<i32, Box<Error>> {
+        let cpuset = match self.config.cpuset {
+            Some(ref cpuset) => cpuset.clone(),
+            None => {
+                match *HOST_CPUS {
+                    Some(cpus) => format!("0-{}", cpus - 1),
+                    None => return Ok(0),
+                }
+            }
+        };
+        let mut jids = vec![outer];
+        jids.extend(inner);
+        for jid in jids {
+            let args = vec![
+                String::from("-l"),
+                cpuset.clone(),
+                String::from("-j"),
+                jid.to_string(),
+            ];
+            debug!("setting cpuset"; "vm" => self.idx.uuid.hyphenated().to_string(), "args" => args.clone().join(" "));
+            let output = Command::new(CPUSET).args(args.clone()).output().expect(
+                "cpuset failed",
+            );
+            if !output.status.success() {
+                crit!("failed to set cpuset"; "vm" => self.idx.uuid.hyphenated().to_string());
+                return Err(GenericError::bx("Could not set cpuset"));
+            }
+        }
+        Ok(0)
+    }
+
+    /// Removes the inner jail with the stop command of its jail.conf,
+    /// removing the outer jail alone would not run it.
+    fn stop_inner(&self) -> Result<i32, Box<Error>> {
+        let uuid = self.idx.uuid.hyphenated().to_string();
+        let args = vec![
+            uuid.clone(),
+            String::from("jail"),
+            String::from("-f"),
+            String::from(INNER_CONF),
+            String::from("-r"),
+            uuid.clone(),
+        ];
+        debug!("stopping inner jail"; "vm" => uuid.clone(), "args" => args.clone().join(" "));
+        let output = Command::new(JEXEC).args(args).output().expect(
+            "jexec failed",
+        );
+        if !output.status.success() {
+            crit!("failed to stop inner jail"; "vm" => uuid);
+            return Err(GenericError::bx("Could not stop inner jail"));
+        }
+        Ok(0)
+    }
+
+    /// Number of the devfs ruleset generated for the jail
+    fn devfs_ruleset(&self) -> Result<u32, Box<Error>> {
+        self.idx.devfs_ruleset.ok_or_else(
+            || GenericError::bx("No devfs ruleset allocated"),
+        )
+    }
+
+    /// Applies the devfs rulesets to the devfs of the outer and the
+    /// inner jail. With `devfs` patterns or DHCP nics a ruleset is
+    /// generated for the jail, otherwise the `devfs_ruleset` parameters
+    /// are used.
+    fn set_devfs(&self, config: &Config) -> Result<i32, Box<Error>> {
+        let mut commands = Vec::new();
+        let (outer, inner) = if self.config.devfs_patterns().is_empty() {
+            let params = self.config.jail_params(config);
+            let ruleset = |params: &Map<String, JailParam>| match params.get("devfs_ruleset") {
+                Some(&JailParam::Int(n)) => n.to_string(),
+                _ => config.settings.devfs_ruleset.to_string(),
+            };
+            (ruleset(&params.outer), ruleset(&params.inner))
+        } else {
+            let ruleset = self.devfs_ruleset()?.to_string();
+            let rule = |args: &[&str]| {
+                let mut res = vec![String::from("rule"), String::from("-s"), ruleset.clone()];
+                res.extend(args.iter().map(|arg| String::from(*arg)));
+                res
+            };
+            // clear leftovers from an earlier start
+            commands.push(rule(&["delset"]));
+            for args in self.config.devfs_rules(config) {
+                let mut command = rule(&[]);
+                command.extend(args);
+                commands.push(command);
+            }
+            (ruleset.clone(), ruleset)
+        };
+        for &(dev, ref ruleset) in [("/root/dev", outer), ("/root/jail/dev", inner)].iter() {
+            let mut mount = String::from("/");
+            mount.push_str(self.idx.root.as_str());
+            mount.push_str(dev);
+            commands.push(vec![
+                String::from("-m"),
+                mount.clone(),
+                String::from("ruleset"),
+                ruleset.clone(),
+            ]);
+            commands.push(vec![
+                String::from("-m"),
+                mount,
+                String::from("rule"),
+                String::from("applyset"),
+            ]);
+        }
+        for args in commands {
+            self.devfs(args)?;
+        }
+        Ok(0)
+    }
+
+    /// Frees the devfs ruleset of the jail
+    fn remove_devfs(&self, config: &Config) -> Result<i32, Box<Error>> {
+        let ruleset = self.devfs_ruleset()?;
+        if ruleset <= config.settings.devfs_ruleset || ruleset < DEVFS_RESERVED {
+            warn!("Not deleting devfs ruleset {} outside of the vmadm range", ruleset;
+                  "vm" => self.idx.uuid.hyphenated().to_string());
+            return Ok(0);
+        }
+        self.devfs(vec![
+            String::from("rule"),
+            String::from("-s"),
+            ruleset.to_string(),
+            String::from("delset"),
+        ])
+    }
+
+    fn devfs(&self, args: Vec<String>) -> Result<i32, Box<Error>> {
+        debug!("devfs"; "vm" => self.idx.uuid.hyphenated().to_string(), "args" => args.clone().join(" "));
+        let output = Command::new(DEVFS).args(args).output().expect(
+            "devfs failed",
+        );
+        if !output.status.success() {
+            crit!("failed to set up devfs rules"; "vm" => self.idx.uuid.hyphenated().to_string());
+            return Err(GenericError::bx("Could not set up devfs rules"));
+        }
+        Ok(0)
+    }
+
+    /// Hot adds a nic to a running jail.
+    fn add_nic(&self, config: &Config, outer: &JailOSEntry, nic: &NIC) -> Result<i32, Box<Error>> {
+        let iface = nic.get_iface(config, &self.idx.uuid)?;
+        let jid = outer.id.to_string();
+        let mut epair = iface.epair.clone();
+        epair.push('b');
+        let args = vec![epair, String::from("vnet"), jid.clone()];
+        debug!("moving epair into jail"; "vm" => self.idx.uuid.hyphenated().to_string(), "args" => args.clone().join(" "));
+        let output = Command::new(IFCONFIG).args(args.clone()).output().expect(
+            "ifconfig failed",
+        );
+        if !output.status.success() {
+            crit!("failed to move interface into jail"; "vm" => self.idx.uuid.hyphenated().to_string());
+            return Err(GenericError::bx("Could not add nic"));
+        }
+        let args = vec![jid, String::from("/bin/sh"), String::from("-c"), iface.start_script.clone()];
+        debug!("configuring nic"; "vm" => self.idx.uuid.hyphenated().to_string(), "args" => args.clone().join(" "));
+        let output = Command::new(JEXEC).args(args.clone()).output().expect(
+            "jexec failed",
+        );
+        if !output.status.success() {
+            crit!("failed to configure interface"; "vm" => self.idx.uuid.hyphenated().to_string());
+            return Err(GenericError::bx("Could not configure nic"));
+        }
+        self.rename_epair(outer.id, &iface)
+    }
+
+    /// Destroys the epair of a nic in a running jail.
+    fn remove_nic(&self, outer: &JailOSEntry, nic: &NIC) -> Result<i32, Box<Error>> {
+        let mut target_name = String::from("j");
+        target_name.push_str(outer.id.to_string().as_str());
+        target_name.push(':');
+        target_name.push_str(nic.interface.as_str());
+        let args = vec![target_name, String::from("destroy")];
+        debug!("destroying epair"; "vm" => self.idx.uuid.hyphenated().to_string(), "args" => args.clone().join(" "));
+        let output = Command::new(IFCONFIG).args(args.clone()).output().expect(
+            "ifconfig failed",
+        );
+        if !output.status.success() {
+            crit!("failed to destroy interface"; "vm" => self.idx.uuid.hyphenated().to_string());
+            return Err(GenericError::bx("Could not remove nic"));
+        }
+        Ok(0)
+    }
+
+    /// Names the host side of an epair after the jail and interface.
+    fn rename_epair(&self, jid: u64, iface: &IFace) -> Result<i32, Box<Error>> {
+        let mut epair = iface.epair.clone();
+        epair.push('a');
+        let mut target_name = String::from("j");
+        target_name.push_str(jid.to_string().as_str());
+        target_name.push(':');
+        target_name.push_str(iface.iface.as_str());
+        let args = vec![epair, String::from("name"), target_name];
+        debug!("renaiming epair"; "vm" => self.idx.uuid.hyphenated().to_string(), "args" => args.clone().join(" "));
+        let output = Command::new(IFCONFIG).args(args.clone()).output().expect(
+            "ifconfig failed",
+        );
+        if !output.status.success() {
+            crit!("failed to rename interface"; "vm" => self.idx.uuid.hyphenated().to_string());
+            return Err(GenericError::bx("Could not rename interface"));
+        }
+        Ok(0)
+    }
+
+    /// Sets the quota of the jails dataset, the quota is in GB and
+    /// 0 means no quota.
+    fn set_quota(&self) -> Result<i32, Box<Error>> {
+        let quota = match self.config.quota {
+            0 => String::from("none"),
+            quota => format!("{}G", quota),
+        };
+        zfs::set(self.idx.root.as_str(), "quota", quota.as_str())
+    }
+
+    /// Brings the routes of a running jail from the old to the
+    /// current configuration.
+    pub fn update_routes(&self, old: &JailConfig) -> Result<i32, Box<Error>> {
+        let outer = match self.outer {
+            Some(outer) => outer,
+            None => return Ok(0),
+        };
+        let mut commands = Vec::new();
+        for (dst, gateway) in old.routes.iter() {
+            if self.config.routes.get(dst) != Some(gateway) {
+                commands.push(old.route_args("delete", dst));
+            }
+        }
+        for (dst, gateway) in self.config.routes.iter() {
+            if old.routes.get(dst) != Some(gateway) {
+                commands.push(self.config.route_args("add", dst));
+            }
+        }
+        for route_args in commands {
+            let mut args = vec![outer.id.to_string(), String::from("/sbin/route")];
+            args.extend(route_args);
+            debug!("updating route"; "vm" => self.idx.uuid.hyphenated().to_string(), "args" => args.clone().join(" "));
+            let output = Command::new(JEXEC).args(args.clone()).output().expect(
+                "route failed",
+            );
+            if !output.status.success() {
+                crit!("failed to update route"; "vm" => self.idx.uuid.hyphenated().to_string());
+                return Err(GenericError::bx("Could not update routes"));
+            }
+        }
         Ok(0)
     }
 
-    fn set_rctl(&self) -> Result<i32, Box<Error>> {
-        let limits = self.config.rctl_limits();
+    fn set_rctl(&self, config: &Config) -> Result<i32, Box<Error>> {
+        let limits = self.config.rctl_limits(config);
         debug!("Setting jail limits"; "vm" => self.idx.uuid.hyphenated().to_string(), "limits" => limits.clone().join(" "));
         let output = Command::new(RCTL).args(limits.clone()).output().expect(
             "limit failed",
@@ -167,7 +466,7 @@ impl<'a> Jail<'a> {
         Ok(0)
     }
 
-    fn mount_devfs(&self) -> Result<i32, Box<Error>> {
+    fn mount_devfs(&self, config: &Config) -> Result<i32, Box<Error>> {
         let mut devfs = String::from("/");
         devfs.push_str(self.idx.root.as_str());
         devfs.push_str("/root/dev");
@@ -197,7 +496,9 @@ impl<'a> Jail<'a> {
             return Err(GenericError::bx("Could not remove resource limits"));
         }
 
-        Ok(0)
+        // the rulesets have to be in place before anything in the jail
+        // runs
+        self.set_devfs(config)
     }
 
     fn remove_rctl(&self) -> Result<i32, Box<Error>> {
@@ -245,76 +546,157 @@ fn start_jail(uuid: &Uuid, args: Vec<String>) -> Result<u64, Box<Error>> {
 
 fn create_args(config: &Config, jail: &Jail) -> Result<CreateArgs, Box<Error>> {
     let uuid = jail.idx.uuid.hyphenated().to_string();
-    let mut name = String::from("name=");
-    name.push_str(uuid.as_str());
-    let mut path = String::from("path=/");
-    path.push_str(jail.idx.root.as_str());
-    path.push_str("/root");
-    let mut hostuuid = String::from("host.hostuuid=");
-    hostuuid.push_str(uuid.as_str());
-    let mut hostname = String::from("host.hostname=");
-    hostname.push_str(jail.config.hostname.as_str());
-    let mut args = vec![
+    let mut ifs = Vec::new();
+    for nic in jail.config.nics.iter() {
+        // see https://lists.freebsd.org/pipermail/freebsd-jail//2016-December/003305.html
+        let iface: IFace = nic.get_iface(config, &jail.idx.uuid)?;
+        ifs.push(iface);
+    }
+    let outer_path = jail_conf_path(config, &jail.idx.uuid);
+    let inner_path = inner_conf_path(jail.idx.root.as_str());
+    write_file(&outer_path, render_outer(config, &jail.config, jail.idx.root.as_str(), &ifs))?;
+    write_file(&inner_path, render_inner(config, &jail.config))?;
+    let args = vec![
         String::from("-i"),
+        String::from("-f"),
+        outer_path.to_string_lossy().into_owned(),
         String::from("-c"),
-        String::from("persist"),
-        name,
-        path,
-        hostuuid,
-        hostname,
+        uuid,
     ];
-    let mut ifs = Vec::new();
+    Ok(CreateArgs { args, ifs })
+}
 
-    // Basic stuff I don't know what it does
-    let mut devfs_ruleset = String::from("devfs_ruleset=");
-    devfs_ruleset.push_str(config.settings.devfs_ruleset.to_string().as_str());
-    args.push(devfs_ruleset);
-    args.push(String::from("securelevel=2"));
-    args.push(String::from("sysvmsg=new"));
-    args.push(String::from("sysvsem=new"));
-    args.push(String::from("sysvshm=new"));
+/// Path of the jail.conf(5) of the outer jail
+pub fn jail_conf_path(config: &Config, uuid: &Uuid) -> PathBuf {
+    let mut path = PathBuf::from(config.settings.conf_dir.as_str());
+    path.push(format!("{}.jail.conf", uuid.hyphenated()));
+    path
+}
 
-    // for nested jails
-    args.push(String::from("allow.raw_sockets"));
-    args.push(String::from("children.max=1"));
+/// Path of the jail.conf(5) of the inner jail inside the outer jail
+static INNER_CONF: &'static str = "/etc/vmadm.jail.conf";
 
+/// Path of the jail.conf(5) of the inner jail on the host
+pub fn inner_conf_path(root: &str) -> PathBuf {
+    let mut path = PathBuf::from("/");
+    path.push(root);
+    path.push("root");
+    path.push(&INNER_CONF[1..]);
+    path
+}
 
-    // let mut exec_stop = String::from("exec.stop=");
-    let mut exec_start = String::from("exec.start=");
-    args.push(String::from("vnet=new"));
-    for nic in jail.config.nics.iter() {
-        // see https://lists.freebsd.org/pipermail/freebsd-jail//2016-December/003305.html
-        let iface: IFace = nic.get_iface(config, &jail.idx.uuid)?;
-        ifs.push(iface.clone());
-        let mut vnet_iface = String::from("vnet.interface=");
-        vnet_iface.push_str(iface.epair.as_str());
-        vnet_iface.push('b');
-
-        args.push(vnet_iface);
-
-        exec_start.push_str(iface.start_script.as_str());
-    }
-    if !jail.config.nics.is_empty() {
-        exec_start.push_str("/sbin/ifconfig lo0 127.0.0.1 up; ");
-    };
-    // inner jail configuration
-    exec_start.push_str("jail -c");
-    exec_start.push_str(" persist name=");
-    exec_start.push_str(uuid.as_str());
-    exec_start.push_str(" host.hostname=");
-    exec_start.push_str(jail.config.hostname.as_str());
-    exec_start.push_str(" path=/jail");
-    exec_start.push_str(" ip4=inherit");
-    exec_start.push_str(" devfs_ruleset=4");
-    exec_start.push_str(" securelevel=2");
-    exec_start.push_str(" sysvmsg=new");
-    exec_start.push_str(" sysvsem=new");
-    exec_start.push_str(" sysvshm=new");
-    exec_start.push_str(" allow.raw_sockets");
-    exec_start.push_str(" exec.start='sh /etc/rc'");
-
-    args.push(exec_start);
-    Ok(CreateArgs { args, ifs })
+fn write_file(path: &PathBuf, content: String) -> Result<i32, Box<Error>> {
+    debug!("writing jail.conf"; "path" => path.to_string_lossy().into_owned());
+    let mut file = File::create(path)?;
+    file.write_all(content.as_bytes())?;
+    Ok(0)
+}
+
+/// Renders the jail.conf(5) of the outer jail, it sets up the network
+/// and starts the inner jail.
+pub fn render_outer(config: &Config, jail: &JailConfig, root: &str, ifs: &[IFace]) -> String {
+    let uuid = jail.uuid.hyphenated().to_string();
+    let mut conf = JailConfWriter::new(uuid.as_str());
+    conf.param("path", format!("/{}/root", root).as_str());
+    conf.flag("persist");
+    conf.param("host.hostuuid", uuid.as_str());
+    conf.param("host.hostname", jail.hostname.as_str());
+    for (name, value) in jail.jail_params(config).outer.iter() {
+        conf.jail_param(name.as_str(), value);
+    }
+    conf.param("vnet", "new");
+    for iface in ifs.iter() {
+        conf.append("vnet.interface", format!("{}b", iface.epair).as_str());
+    }
+    for iface in ifs.iter() {
+        conf.append("exec.start", iface.start_script.as_str());
+    }
+    if !ifs.is_empty() {
+        conf.append("exec.start", "/sbin/ifconfig lo0 127.0.0.1 up");
+    }
+    let routes = jail.route_script();
+    if !routes.is_empty() {
+        conf.append("exec.start", routes.as_str());
+    }
+    conf.append(
+        "exec.start",
+        format!("jail -f {} -c {}", INNER_CONF, uuid).as_str(),
+    );
+    conf.finish()
+}
+
+/// Renders the jail.conf(5) of the inner jail
+pub fn render_inner(config: &Config, jail: &JailConfig) -> String {
+    let uuid = jail.uuid.hyphenated().to_string();
+    let mut conf = JailConfWriter::new(uuid.as_str());
+    conf.param("path", "/jail");
+    conf.flag("persist");
+    conf.param("host.hostname", jail.hostname.as_str());
+    conf.param("ip4", "inherit");
+    for (name, value) in jail.jail_params(config).inner.iter() {
+        conf.jail_param(name.as_str(), value);
+    }
+    conf.param("exec.start", jail.exec_start().as_str());
+    if let Some(stop) = jail.exec_stop() {
+        conf.param("exec.stop", stop.as_str());
+    }
+    conf.finish()
+}
+
+/// Writes the block of a single jail in a jail.conf(5) file
+struct JailConfWriter {
+    out: String,
+}
+
+impl JailConfWriter {
+    fn new(name: &str) -> Self {
+        JailConfWriter {
+            out: format!("# generated by vmadm, changes are overwritten\n{} {{\n", name),
+        }
+    }
+
+    fn flag(&mut self, name: &str) {
+        self.out.push_str(format!("    {};\n", name).as_str());
+    }
+
+    fn param(&mut self, name: &str, value: &str) {
+        self.out.push_str(format!("    {} = {};\n", name, conf_quote(value)).as_str());
+    }
+
+    fn append(&mut self, name: &str, value: &str) {
+        self.out.push_str(format!("    {} += {};\n", name, conf_quote(value)).as_str());
+    }
+
+    fn jail_param(&mut self, name: &str, value: &JailParam) {
+        match *value {
+            JailParam::Bool(_) => self.flag(value.arg(name).as_str()),
+            JailParam::Int(n) => self.out.push_str(format!("    {} = {};\n", name, n).as_str()),
+            JailParam::Str(ref s) => self.param(name, s.as_str()),
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.out.push_str("}\n");
+        self.out
+    }
+}
+
+/// Quotes a value for jail.conf(5), `$` is escaped so no variables get
+/// expanded.
+fn conf_quote(value: &str) -> String {
+    let mut res = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' | '\\' | '$' => {
+                res.push('\\');
+                res.push(c)
+            }
+            '\n' => res.push_str("\\n"),
+            _ => res.push(c),
+        }
+    }
+    res.push('"');
+    res
 }
 
 /// reads the zfs datasets in a pool
@@ -367,3 +749,82 @@ fn deconstruct_entry(line: &str) -> Result<JailOSEntry, Box<Error>> {
         id: id,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use jails::*;
+    use config::Settings;
+    use toml;
+
+    fn config() -> Config {
+        let settings: Settings = toml::from_str(
+            "pool = \"zroot/jails\"\n[networks]\nadmin = \"bridge0\"\n[jail_params.inner]\n\"allow.mlock\" = true\n",
+        ).unwrap();
+        Config {
+            settings: settings,
+            req_id: String::from("test"),
+        }
+    }
+
+    fn conf(config: &Config) -> JailConfig {
+        let json = r#"{
+            "uuid": "0a4bd1a4-3a5c-4e33-8a21-6e9c1a34c8e5",
+            "image_uuid": "616d4ab2-832c-11e7-9392-784f438c8d54",
+            "alias": "test",
+            "hostname": "test",
+            "max_physical_memory": 1024,
+            "cpu_cap": 100,
+            "quota": 5,
+            "init_name": "/usr/local/bin/app --greeting \"hello $USER\"",
+            "stop_command": "pkill app",
+            "exec_env": {"APP_ENV": "it's prod"},
+            "jail_params": {"outer": {"securelevel": 3}},
+            "routes": {"10.0.0.0/8": "192.168.254.1"},
+            "nics": [
+                {
+                    "interface": "net0",
+                    "mac": "02:00:00:00:00:01",
+                    "nic_tag": "admin",
+                    "ip": "192.168.254.254",
+                    "netmask": "255.255.255.0",
+                    "gateway": "192.168.254.1",
+                    "primary": true
+                }
+            ]
+        }"#;
+        JailConfig::from_reader(config, json.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn render_outer_conf() {
+        let config = config();
+        let conf = conf(&config);
+        let ifs: Vec<IFace> = conf.nics
+            .iter()
+            .map(|nic| {
+                IFace {
+                    iface: nic.interface.clone(),
+                    epair: String::from("epair0"),
+                    start_script: nic.start_script("epair0"),
+                }
+            })
+            .collect();
+        assert_eq!(
+            include_str!("golden/outer.conf"),
+            render_outer(&config, &conf, "zroot/jails/0a4bd1a4-3a5c-4e33-8a21-6e9c1a34c8e5", &ifs)
+        );
+    }
+
+    #[test]
+    fn render_inner_conf() {
+        let config = config();
+        let conf = conf(&config);
+        assert_eq!(include_str!("golden/inner.conf"), render_inner(&config, &conf));
+    }
+
+    #[test]
+    fn quote() {
+        assert_eq!(r#""a \"b\" \\ \$c""#, conf_quote(r#"a "b" \ $c"#));
+        assert_eq!(r#""a\nb""#, conf_quote("a\nb"));
+    }
+}
@@ -14,14 +14,18 @@ use prettytable::row::Row;
 use prettytable::cell::Cell;
 use uuid::Uuid;
 use serde_json;
+use chrono::{DateTime, Utc};
 
 use jails::Jail;
 use jails;
-use jail_config::JailConfig;
+use jail_config::{JailConfig, new_mac};
 
-use errors::{NotFoundError, ConflictError, GenericError};
+use errors::{NotFoundError, ConflictError, GenericError, ValidationError, ValidationErrors};
 use config::Config;
 
+/// How often we try to generate a mac address that is not taken yet
+const MAC_TRIES: usize = 100;
+
 /// `JailDB` index entry
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct IdxEntry {
@@ -32,6 +36,9 @@ pub struct IdxEntry {
     pub root: String,
     state: String,
     jail_type: String,
+    /// devfs ruleset generated for the jail
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub devfs_ruleset: Option<u32>,
 }
 
 
@@ -42,6 +49,19 @@ impl PartialEq for IdxEntry {
     }
 }
 
+/// A revision of a jail config as written by a create or update
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Revision {
+    /// revision number, increasing per jail
+    pub rev: u64,
+    /// time the revision was written
+    pub timestamp: DateTime<Utc>,
+    /// id of the request that wrote the revision
+    pub req_id: String,
+    /// the jail config
+    pub config: JailConfig,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct Index {
     pub version: u32,
@@ -79,11 +99,22 @@ impl<'a> JDB<'a> {
             Ok(file) => {
                 let index: Index = serde_json::from_reader(file)?;
                 debug!("Found {} entries", index.entries.len());
-                Ok(JDB {
+                let mut db = JDB {
                     index: index,
                     config: config,
                     jails: jails::list()?,
-                })
+                };
+                // entries from before devfs rulesets were allocated
+                if db.index.entries.iter().any(|e| e.devfs_ruleset.is_none()) {
+                    for i in 0..db.index.entries.len() {
+                        if db.index.entries[i].devfs_ruleset.is_none() {
+                            let ruleset = db.free_devfs_ruleset()?;
+                            db.index.entries[i].devfs_ruleset = Some(ruleset);
+                        }
+                    }
+                    db.save()?;
+                }
+                Ok(db)
             }
             Err(_) => {
                 warn!("No database found creating new one.");
@@ -118,16 +149,19 @@ impl<'a> JDB<'a> {
                 let mut root = String::from(self.config.settings.pool.as_str());
                 root.push('/');
                 root.push_str(&config.uuid.hyphenated().to_string());
+                let devfs_ruleset = self.free_devfs_ruleset()?;
                 let e = IdxEntry {
                     version: 0,
                     uuid: config.uuid.clone(),
                     state: String::from("stopped"),
                     jail_type: String::from("base"),
                     root: root.clone(),
+                    devfs_ruleset: Some(devfs_ruleset),
                 };
                 self.index.entries.push(e);
                 self.save()?;
                 serde_json::to_writer(file, &config)?;
+                self.record_history(&config);
                 // This is ugly but I don't know any better.
                 Ok(IdxEntry {
                     version: 0,
@@ -135,6 +169,7 @@ impl<'a> JDB<'a> {
                     state: String::from("stopped"),
                     jail_type: String::from("base"),
                     root: root.clone(),
+                    devfs_ruleset: Some(devfs_ruleset),
                 })
             }
             Some(_) => {
@@ -144,8 +179,19 @@ impl<'a> JDB<'a> {
         }
     }
 
-    /// Inserts a config into the database, writes the config file
-    /// and adds it to the index.
+    /// Checks a config for errors and conflicts with other jails.
+    pub fn validate(self: &'a JDB<'a>, config: &JailConfig) -> Option<Vec<ValidationError>> {
+        let mut errors = config.errors(self.config).unwrap_or_default();
+        errors.append(&mut self.conflicts(config));
+        if errors.is_empty() {
+            None
+        } else {
+            Some(errors)
+        }
+    }
+
+    /// Updates the config file of a jail, the config is validated
+    /// and nothing is written if it contains errors.
     pub fn update(self: &'a mut JDB<'a>, config: JailConfig) -> Result<i32, Box<Error>> {
         debug!("Updating vm"; "vm" => &config.uuid.hyphenated().to_string());
         match self.find(&config.uuid) {
@@ -155,13 +201,16 @@ impl<'a> JDB<'a> {
             }
 
             Some(_) => {
+                if let Some(errors) = self.validate(&config) {
+                    warn!("Refusing invalid update"; "vm" => &config.uuid.hyphenated().to_string());
+                    return Err(ValidationErrors::bx(errors));
+                }
                 let mut path = PathBuf::from(self.config.settings.conf_dir.as_str());
                 path.push(config.uuid.hyphenated().to_string());
                 path.set_extension("json");
                 debug!("Updating config file"; "file" => path.to_str(), "vm" => &config.uuid.hyphenated().to_string());
-                let file = File::create(path)?;
-                serde_json::to_writer(file, &config)?;
-                // This is ugly but I don't know any better.
+                write_config(&path, &config)?;
+                self.record_history(&config);
                 Ok(0)
             }
         }
@@ -179,6 +228,7 @@ impl<'a> JDB<'a> {
                 path.push(uuid.hyphenated().to_string());
                 path.set_extension("json");
                 fs::remove_file(&path)?;
+                let _ = fs::remove_dir_all(self.history_dir(uuid));
                 self.index.entries.remove(index);
                 self.save()?;
                 Ok(index)
@@ -186,6 +236,125 @@ impl<'a> JDB<'a> {
         }
     }
 
+    /// Lists the recorded revisions of a jail config, oldest first.
+    pub fn history(self: &'a JDB<'a>, uuid: &Uuid) -> Result<Vec<Revision>, Box<Error>> {
+        let mut res = Vec::new();
+        for rev in self.revisions(uuid)? {
+            res.push(self.revision(uuid, rev)?);
+        }
+        Ok(res)
+    }
+
+    /// Reads a single revision of a jail config.
+    pub fn revision(self: &'a JDB<'a>, uuid: &Uuid, rev: u64) -> Result<Revision, Box<Error>> {
+        let mut path = self.history_dir(uuid);
+        path.push(rev.to_string());
+        path.set_extension("json");
+        match File::open(path) {
+            Ok(file) => Ok(serde_json::from_reader(file)?),
+            Err(_) => Err(GenericError::bx("Unknown revision")),
+        }
+    }
+
+    /// Prints the revisions of a jail config
+    pub fn print_history(
+        self: &'a JDB<'a>,
+        uuid: &Uuid,
+        headerless: bool,
+        parsable: bool,
+    ) -> Result<i32, Box<Error>> {
+        let mut table = Table::new();
+        table.set_format(*format::consts::FORMAT_CLEAN);
+        if !headerless {
+            if parsable {
+                println!("{}:{}:{}", "REV", "DATE", "REQ_ID");
+            } else {
+                table.add_row(row!["REV", "DATE", "REQ_ID"]);
+            }
+        }
+        for revision in self.history(uuid)? {
+            let date = revision.timestamp.to_rfc3339();
+            if parsable {
+                println!("{}:{}:{}", revision.rev, date, revision.req_id);
+            } else {
+                table.add_row(Row::new(vec![
+                    Cell::new(revision.rev.to_string().as_str()),
+                    Cell::new(date.as_str()),
+                    Cell::new(revision.req_id.as_str()),
+                ]));
+            }
+        }
+        if !parsable {
+            table.printstd()
+        };
+        Ok(0)
+    }
+
+    /// Records a written config as a new revision and drops the oldest
+    /// ones beyond `history_size`, failures are only warned about.
+    fn record_history(self: &'a JDB<'a>, config: &JailConfig) {
+        if self.config.settings.history_size == 0 {
+            return;
+        }
+        match self.write_revision(config) {
+            Ok(rev) => debug!("Recorded revision {}", rev; "vm" => config.uuid.hyphenated().to_string()),
+            Err(e) => warn!("Failed to record revision: {}", e; "vm" => config.uuid.hyphenated().to_string()),
+        }
+    }
+
+    fn write_revision(self: &'a JDB<'a>, config: &JailConfig) -> Result<u64, Box<Error>> {
+        let dir = self.history_dir(&config.uuid);
+        fs::create_dir_all(&dir)?;
+        let mut revs = self.revisions(&config.uuid)?;
+        let rev = revs.last().map(|rev| rev + 1).unwrap_or(1);
+        let revision = Revision {
+            rev: rev,
+            timestamp: Utc::now(),
+            req_id: self.config.req_id.clone(),
+            config: config.clone(),
+        };
+        let mut path = dir.clone();
+        path.push(rev.to_string());
+        path.set_extension("json");
+        let file = File::create(path)?;
+        serde_json::to_writer(file, &revision)?;
+        revs.push(rev);
+        while revs.len() > self.config.settings.history_size {
+            let mut path = dir.clone();
+            path.push(revs.remove(0).to_string());
+            path.set_extension("json");
+            fs::remove_file(path)?;
+        }
+        Ok(rev)
+    }
+
+    /// The recorded revision numbers of a jail, sorted.
+    fn revisions(self: &'a JDB<'a>, uuid: &Uuid) -> Result<Vec<u64>, Box<Error>> {
+        let dir = self.history_dir(uuid);
+        let mut revs = Vec::new();
+        if !dir.is_dir() {
+            return Ok(revs);
+        }
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if let Some(rev) = path.file_stem().and_then(|stem| stem.to_str()).and_then(
+                |stem| stem.parse::<u64>().ok(),
+            )
+            {
+                revs.push(rev);
+            }
+        }
+        revs.sort();
+        Ok(revs)
+    }
+
+    fn history_dir(self: &'a JDB<'a>, uuid: &Uuid) -> PathBuf {
+        let mut path = PathBuf::from(self.config.settings.conf_dir.as_str());
+        path.push("history");
+        path.push(uuid.hyphenated().to_string());
+        path
+    }
+
     /// Reads the config file for a given entry
     fn config(self: &'a JDB<'a>, entry: &IdxEntry) -> Result<JailConfig, Box<Error>> {
         debug!("Loading vm config"; "vm" => &entry.uuid.hyphenated().to_string());
@@ -193,7 +362,7 @@ impl<'a> JDB<'a> {
         config_path.push(entry.uuid.hyphenated().to_string());
         config_path.set_extension("json");
         match config_path.to_str() {
-            Some(path) => JailConfig::from_file(self.config, path),
+            Some(path) => JailConfig::from_file(path),
             None => Err(GenericError::bx("could not generate vm config path")),
         }
     }
@@ -230,6 +399,99 @@ impl<'a> JDB<'a> {
         }
     }
 
+    /// Assigns a mac address to every nic of a config that does not
+    /// have one yet, making sure it is not used by any other jail.
+    pub fn assign_macs(self: &'a JDB<'a>, config: &mut JailConfig) -> Result<(), Box<Error>> {
+        let mut taken: Vec<String> = Vec::new();
+        for other in self.others(&config.uuid) {
+            for nic in other.nics {
+                taken.push(nic.mac.to_lowercase());
+            }
+        }
+        for nic in config.nics.iter() {
+            taken.push(nic.mac.to_lowercase());
+        }
+        for nic in config.nics.iter_mut() {
+            if nic.mac.is_empty() {
+                let mut mac = new_mac(self.config.settings.mac_oui.as_str())?;
+                let mut tries = 1;
+                while taken.contains(&mac) {
+                    if tries >= MAC_TRIES {
+                        return Err(GenericError::bx("Could not find a free mac address"));
+                    }
+                    mac = new_mac(self.config.settings.mac_oui.as_str())?;
+                    tries += 1;
+                }
+                taken.push(mac.clone());
+                nic.mac = mac;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks a config for mac addresses that are used by other jails
+    /// and IPs used by other jails on the same network.
+    pub fn conflicts(self: &'a JDB<'a>, config: &JailConfig) -> Vec<ValidationError> {
+        let mut errors = Vec::new();
+        let others = self.others(&config.uuid);
+        for (i, nic) in config.nics.iter().enumerate() {
+            let ips = nic.static_ips();
+            for other in others.iter() {
+                for other_nic in other.nics.iter() {
+                    if !nic.mac.is_empty() &&
+                        nic.mac.to_lowercase() == other_nic.mac.to_lowercase()
+                    {
+                        errors.push(ValidationError::new(
+                            format!("nics.{}.mac", i).as_str(),
+                            format!("Mac already used by jail {}", other.uuid).as_str(),
+                        ))
+                    }
+                    if nic.nic_tag != other_nic.nic_tag || nic.vlan != other_nic.vlan {
+                        continue;
+                    }
+                    let other_ips = other_nic.static_ips();
+                    for ip in ips.iter().filter(|ip| other_ips.contains(ip)) {
+                        errors.push(ValidationError::new(
+                            format!("nics.{}.ip", i).as_str(),
+                            format!("IP {} already used by jail {}", ip, other.uuid).as_str(),
+                        ))
+                    }
+                }
+            }
+        }
+        errors
+    }
+
+    /// Loads the configs of all jails except the one with the given uuid
+    fn others(self: &'a JDB<'a>, uuid: &Uuid) -> Vec<JailConfig> {
+        let mut configs = Vec::new();
+        for entry in self.index.entries.iter() {
+            if entry.uuid == *uuid {
+                continue;
+            }
+            match self.config(entry) {
+                Ok(config) => configs.push(config),
+                Err(e) => warn!("Failed to load config: {}", e; "vm" => entry.uuid.hyphenated().to_string()),
+            }
+        }
+        configs
+    }
+
+    /// The lowest devfs ruleset from `devfs_ruleset_base` on no jail uses
+    fn free_devfs_ruleset(&self) -> Result<u32, Box<Error>> {
+        let mut ruleset = self.config.settings.devfs_ruleset_base;
+        while self.index.entries.iter().any(
+            |e| e.devfs_ruleset == Some(ruleset),
+        )
+        {
+            ruleset += 1;
+        }
+        if ruleset > 65535 {
+            return Err(GenericError::bx("No free devfs ruleset"));
+        }
+        Ok(ruleset)
+    }
+
     /// Finds an entry for a given uuid
     fn find(self: &'a JDB<'a>, uuid: &Uuid) -> Option<usize> {
         self.index.entries.iter().position(|x| x.uuid == *uuid)
@@ -291,3 +553,161 @@ impl<'a> JDB<'a> {
         Ok(0)
     }
 }
+
+/// Writes a config file through a temporary file that is renamed
+/// into place, so a failed write never leaves a partial config.
+fn write_config(path: &PathBuf, config: &JailConfig) -> Result<(), Box<Error>> {
+    let mut tmp = path.clone();
+    tmp.set_extension("json.tmp");
+    {
+        let file = File::create(&tmp)?;
+        serde_json::to_writer(file, config)?;
+    }
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use jdb::*;
+    use std::env;
+    use std::collections::BTreeMap as Map;
+    use config::Settings;
+    use jail_config::JailParams;
+
+    fn config(history_size: usize) -> Config {
+        let mut dir = env::temp_dir();
+        dir.push(format!("vmadm-jdb-{}", Uuid::new_v4().hyphenated()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut networks = Map::new();
+        networks.insert(String::from("admin"), String::from("bridge0"));
+        Config {
+            settings: Settings {
+                pool: String::from("zroot/jails"),
+                repo: String::from("https://bsd.project-fifo.net/images"),
+                conf_dir: dir.to_string_lossy().into_owned(),
+                image_dir: String::from("/var/imgadm/images"),
+                devfs_ruleset: 4,
+                devfs_ruleset_base: 1000,
+                mac_oui: String::from("02"),
+                strict: false,
+                history_size: history_size,
+                thresholds: Map::new(),
+                jail_params: JailParams::new(),
+                hooks: Map::new(),
+                networks: networks,
+            },
+            req_id: String::from("test"),
+        }
+    }
+
+    fn jail(config: &Config) -> JailConfig {
+        let json = r#"{
+            "image_uuid": "616d4ab2-832c-11e7-9392-784f438c8d54",
+            "alias": "rev1",
+            "hostname": "test",
+            "max_physical_memory": 1024,
+            "cpu_cap": 100,
+            "quota": 5
+        }"#;
+        JailConfig::from_reader(config, json.as_bytes()).unwrap()
+    }
+
+    /// Creates a jail and updates it `updates` times, the alias of
+    /// each revision is `rev<n>`.
+    fn create(config: &Config, updates: u64) -> Uuid {
+        let mut conf = jail(config);
+        let uuid = conf.uuid.clone();
+        {
+            let mut db = JDB::open(config).unwrap();
+            db.insert(conf.clone()).unwrap();
+        }
+        for n in 0..updates {
+            conf.alias = format!("rev{}", n + 2);
+            let mut db = JDB::open(config).unwrap();
+            db.update(conf.clone()).unwrap();
+        }
+        uuid
+    }
+
+    fn revisions(config: &Config, uuid: &Uuid) -> Vec<u64> {
+        let db = JDB::open(config).unwrap();
+        db.revisions(uuid).unwrap()
+    }
+
+    fn cleanup(config: &Config) {
+        fs::remove_dir_all(config.settings.conf_dir.as_str()).unwrap();
+    }
+
+    #[test]
+    fn revisions_increase() {
+        let config = config(10);
+        let uuid = create(&config, 2);
+        assert_eq!(vec![1, 2, 3], revisions(&config, &uuid));
+        {
+            let db = JDB::open(&config).unwrap();
+            let aliases: Vec<String> = db.history(&uuid)
+                .unwrap()
+                .into_iter()
+                .map(|revision| revision.config.alias)
+                .collect();
+            assert_eq!(vec!["rev1", "rev2", "rev3"], aliases);
+        }
+        cleanup(&config);
+    }
+
+    #[test]
+    fn history_pruned() {
+        let config = config(2);
+        let uuid = create(&config, 3);
+        assert_eq!(vec![3, 4], revisions(&config, &uuid));
+        cleanup(&config);
+    }
+
+    #[test]
+    fn history_disabled() {
+        let config = config(0);
+        let uuid = create(&config, 2);
+        assert!(revisions(&config, &uuid).is_empty());
+        {
+            let db = JDB::open(&config).unwrap();
+            assert!(!db.history_dir(&uuid).exists());
+        }
+        cleanup(&config);
+    }
+
+    #[test]
+    fn rollback() {
+        let config = config(10);
+        let uuid = create(&config, 1);
+        let old = {
+            let db = JDB::open(&config).unwrap();
+            db.revision(&uuid, 1).unwrap().config
+        };
+        {
+            let mut db = JDB::open(&config).unwrap();
+            db.update(old).unwrap();
+        }
+        assert_eq!(vec![1, 2, 3], revisions(&config, &uuid));
+        {
+            let db = JDB::open(&config).unwrap();
+            assert_eq!("rev1", db.revision(&uuid, 3).unwrap().config.alias);
+            assert_eq!("rev1", db.get(&uuid).unwrap().config.alias);
+        }
+        cleanup(&config);
+    }
+
+    #[test]
+    fn unknown_revision() {
+        let config = config(10);
+        let uuid = create(&config, 0);
+        {
+            let db = JDB::open(&config).unwrap();
+            match db.revision(&uuid, 42) {
+                Err(e) => assert_eq!("Unknown revision", e.to_string()),
+                Ok(_) => panic!("revision 42 should not exist"),
+            }
+        }
+        cleanup(&config);
+    }
+}
@@ -51,10 +51,13 @@ use std::error::Error;
 use std::io;
 use std::fs::OpenOptions;
 use std::fs::File;
+use std::fs;
+use std::io::Read;
 
 use aud::{Failure, Adventure, Saga};
 
 use std::process::Command;
+use std::os::unix::process::ExitStatusExt;
 
 mod zfs;
 mod images;
@@ -70,10 +73,12 @@ mod jdb;
 use jdb::{JDB, IdxEntry};
 
 mod config;
-use config::Config;
+use config::{Config, CONFIG};
 
 mod errors;
-use errors::GenericError;
+
+mod hooks;
+use errors::{GenericError, ValidationError, ValidationErrors};
 
 #[cfg(target_os = "freebsd")]
 static JEXEC: &'static str = "jexec";
@@ -161,14 +166,31 @@ fn run() -> i32 {
 
     let drain = slog::Duplicate::new(file_drain, term_drain).fuse();
 
-    let root = slog::Logger::root(
-        drain,
-        o!("req_id" => Uuid::new_v4().hyphenated().to_string()),
-    );
+    let req_id = Uuid::new_v4().hyphenated().to_string();
+    let root = slog::Logger::root(drain, o!("req_id" => req_id.clone()));
 
     let _guard = slog_scope::set_global_logger(root);
 
-    let config: Config = Config::new().unwrap();
+    let mut config: Config = match Config::new() {
+        Ok(config) => config,
+        Err(e) => {
+            crit!("invalid config: {}", e);
+            match e.downcast_ref::<ValidationErrors>() {
+                Some(validation) => {
+                    eprintln!("invalid config {}:", CONFIG);
+                    for e in validation.errors() {
+                        eprintln!("{}", e);
+                    }
+                }
+                None => eprintln!("invalid config {}: {}", CONFIG, e),
+            }
+            return 1;
+        }
+    };
+    config.req_id = req_id;
+    if matches.is_present("strict") {
+        config.settings.strict = true;
+    }
     let r = if matches.is_present("startup") {
         match matches.subcommand() {
             ("", None) => startup(&config),
@@ -186,7 +208,14 @@ fn run() -> i32 {
             ("get", Some(get_matches)) => get(&config, get_matches),
             ("info", Some(info_matches)) => info(&config, info_matches),
             ("console", Some(console_matches)) => console(&config, console_matches),
+            ("exec", Some(exec_matches)) => exec(&config, exec_matches),
             ("images", Some(image_matches)) => images(&config, image_matches),
+            ("validate", Some(validate_matches)) => validate(&config, validate_matches),
+            ("history", Some(history_matches)) => history(&config, history_matches),
+            ("diff", Some(diff_matches)) => diff(&config, diff_matches),
+            ("rollback-config", Some(rollback_matches)) => {
+                rollback_config(&config, rollback_matches)
+            }
             ("", None) => {
                 help_app.print_help().unwrap();
                 println!();
@@ -242,7 +271,7 @@ fn reboot(conf: &Config, matches: &clap::ArgMatches) -> Result<i32, Box<Error>>
         }
         Ok(jail) => {
             println!("Rebooting jail {}", uuid);
-            jail.stop()?;
+            jail.stop(conf)?;
             jail.start(conf)
         }
     }
@@ -254,6 +283,7 @@ fn get(conf: &Config, matches: &clap::ArgMatches) -> Result<i32, Box<Error>> {
     debug!("Starting jail {}", uuid.hyphenated().to_string());
     match db.get(&uuid) {
         Err(e) => Err(e),
+        Ok(ref jail) if matches.is_present("jail_conf") => print_jail_conf(conf, jail),
         Ok(Jail { config: conf, .. }) => {
             let j = serde_json::to_string_pretty(&conf)?;
             println!("{}\n", j);
@@ -262,6 +292,20 @@ fn get(conf: &Config, matches: &clap::ArgMatches) -> Result<i32, Box<Error>> {
     }
 }
 
+fn print_jail_conf(conf: &Config, jail: &Jail) -> Result<i32, Box<Error>> {
+    let outer = jails::jail_conf_path(conf, &jail.idx.uuid);
+    let inner = jails::inner_conf_path(jail.idx.root.as_str());
+    for path in [outer, inner].iter() {
+        let mut content = String::new();
+        match File::open(path) {
+            Ok(mut file) => file.read_to_string(&mut content)?,
+            Err(_) => return Err(GenericError::bx("No jail.conf written, the jail was not started yet")),
+        };
+        println!("# {}\n{}", path.display(), content);
+    }
+    Ok(0)
+}
+
 fn info(conf: &Config, matches: &clap::ArgMatches) -> Result<i32, Box<Error>> {
     let db = JDB::open(conf)?;
     let uuid_string = value_t!(matches, "uuid", String).unwrap();
@@ -288,18 +332,82 @@ fn console(conf: &Config, matches: &clap::ArgMatches) -> Result<i32, Box<Error>>
             Err(GenericError::bx("VM is not running"))
         }
         Ok(Jail { inner: Some(jid), .. }) => {
-            let mut child = Command::new(JEXEC)
-                .args(&[jid.id.to_string().as_str(), "/bin/csh"])
-                .spawn()
-                .expect("failed to execute jexec");
-            let ecode = child.wait().expect("failed to wait on child");
-            if ecode.success() {
-                Ok(0)
-            } else {
-                Err(GenericError::bx("Failed to execute jail console"))
+            match jexec(vec![jid.id.to_string(), String::from("/bin/csh")]) {
+                Ok(0) => Ok(0),
+                Ok(_) => Err(GenericError::bx("Failed to execute jail console")),
+                Err(e) => Err(e),
+            }
+        }
+    }
+}
+
+fn exec(conf: &Config, matches: &clap::ArgMatches) -> Result<i32, Box<Error>> {
+    let db = JDB::open(conf)?;
+    let uuid = value_t!(matches, "uuid", Uuid)?;
+    let jail = db.get(&uuid)?;
+    let target = if matches.is_present("outer") {
+        jail.outer
+    } else {
+        jail.inner
+    };
+    let jid = match target {
+        Some(jid) => jid.id,
+        None => return Err(GenericError::bx("VM is not running")),
+    };
+    let env: Vec<&str> = match matches.values_of("env") {
+        Some(env) => env.collect(),
+        None => Vec::new(),
+    };
+    if matches.is_present("outer") && !env.is_empty() {
+        // the outer jail has no env binary to set them with
+        return Err(GenericError::bx("Environment variables can not be set in the outer jail"));
+    }
+    let command: Vec<&str> = matches.values_of("command").unwrap().collect();
+    let args = exec_args(jid, matches.value_of("user"), &env, &command)?;
+    debug!("exec in jail"; "vm" => uuid.hyphenated().to_string(), "args" => args.clone().join(" "));
+    jexec(args)
+}
+
+/// Builds the jexec arguments to run a command in a jail.
+fn exec_args(
+    jid: u64,
+    user: Option<&str>,
+    env: &[&str],
+    command: &[&str],
+) -> Result<Vec<String>, Box<Error>> {
+    let mut args = Vec::new();
+    if let Some(user) = user {
+        // -U looks the user up inside of the jail
+        args.push(String::from("-U"));
+        args.push(String::from(user));
+    }
+    args.push(jid.to_string());
+    if !env.is_empty() {
+        args.push(String::from("env"));
+        for var in env {
+            match var.find('=') {
+                Some(idx) if idx > 0 => args.push(String::from(*var)),
+                _ => return Err(GenericError::bx("Environment variables need the form VAR=value")),
             }
         }
     }
+    args.extend(command.iter().map(|c| String::from(*c)));
+    Ok(args)
+}
+
+/// Runs jexec with stdin, stdout and stderr of vmadm and returns the
+/// exit code of the command, 128 + the signal if it was killed.
+fn jexec(args: Vec<String>) -> Result<i32, Box<Error>> {
+    let status = Command::new(JEXEC).args(args).status()?;
+    Ok(exit_code(status.code(), status.signal()))
+}
+
+/// Maps an exit code or the killing signal to a shell style exit code.
+fn exit_code(code: Option<i32>, signal: Option<i32>) -> i32 {
+    match code {
+        Some(code) => code,
+        None => 128 + signal.unwrap_or(0),
+    }
 }
 
 fn stop(conf: &Config, matches: &clap::ArgMatches) -> Result<i32, Box<Error>> {
@@ -315,7 +423,7 @@ fn stop(conf: &Config, matches: &clap::ArgMatches) -> Result<i32, Box<Error>> {
         }
         Ok(jail) => {
             println!("Stopping jail {}", uuid);
-            jail.stop()
+            jail.stop(conf)
         }
     }
 }
@@ -329,30 +437,127 @@ fn list(conf: &Config, matches: &clap::ArgMatches) -> Result<i32, Box<Error>> {
 }
 
 fn update(conf: &Config, matches: &clap::ArgMatches) -> Result<i32, Box<Error>> {
-    let mut db = JDB::open(conf)?;
+    let db = JDB::open(conf)?;
     let uuid_string = value_t!(matches, "uuid", String).unwrap();
     let uuid = Uuid::parse_str(uuid_string.as_str()).unwrap();
     let update = match value_t!(matches, "file", String) {
+        Err(_) if matches.is_present("properties") => {
+            let properties = values_t!(matches, "properties", String).unwrap();
+            debug!("Reading from arguments"; "properties" => properties.join(" "));
+            update::JailUpdate::from_args(conf, properties)?
+        }
         Err(_) => {
             debug!("Reading from STDIN");
-            update::JailUpdate::from_reader(io::stdin())?
+            update::JailUpdate::from_reader(conf, io::stdin())?
         }
         Ok(file) => {
             debug!("Reading from file"; "file" => file.clone() );
-            update::JailUpdate::from_reader(File::open(file)?)?
+            update::JailUpdate::from_reader(conf, File::open(file)?)?
         }
     };
     match db.get(&uuid) {
         Err(e) => Err(e),
-        Ok(Jail { config: c, .. }) => {
-            let c = update.apply(c);
-            db.update(c)
+        Ok(Jail { config: old, .. }) => {
+            if let Some(errors) = update.selector_errors(&old) {
+                return Err(ValidationErrors::bx(errors));
+            }
+            let mut c = update.apply(old.clone());
+            c.normalize();
+            db.assign_macs(&mut c)?;
+            if matches.is_present("dry_run") {
+                if let Some(errors) = db.validate(&c) {
+                    return Err(ValidationErrors::bx(errors));
+                }
+                print_changes(c.diff(&old));
+                return Ok(0);
+            }
+            save_update(conf, &old, c)
         }
     }
 }
 
+/// Applies an updated config to the jail if it is running and writes
+/// it, if applying fails the changes are rolled back and the config
+/// is not written.
+fn save_update(conf: &Config, old: &JailConfig, new: JailConfig) -> Result<i32, Box<Error>> {
+    let uuid = new.uuid.clone();
+    // the running jail is changed before the config is written so a
+    // failed change does not leave a config behind that isn't applied
+    let reboot = {
+        let db = JDB::open(conf)?;
+        if let Some(errors) = db.validate(&new) {
+            return Err(ValidationErrors::bx(errors));
+        }
+        let mut jail = db.get(&uuid)?;
+        jail.config = new.clone();
+        match jail.update(conf, old) {
+            Ok(reboot) => reboot,
+            Err(e) => {
+                crit!("Rolling back update: {}", e; "vm" => uuid.hyphenated().to_string());
+                let jail = Jail { config: old.clone(), ..jail };
+                if let Err(e) = jail.update(conf, &new) {
+                    crit!("Failed to roll back update: {}", e; "vm" => uuid.hyphenated().to_string());
+                }
+                return Err(e);
+            }
+        }
+    };
+    {
+        let mut db = JDB::open(conf)?;
+        db.update(new)?;
+    }
+    if !reboot.is_empty() {
+        println!("Changes to {} take effect after a reboot", reboot.join(", "));
+    }
+    Ok(0)
+}
+
+fn print_changes(changes: Vec<jail_config::Change>) {
+    if changes.is_empty() {
+        println!("No changes");
+    }
+    for change in changes.iter() {
+        println!("{}", change);
+    }
+}
+
+fn history(conf: &Config, matches: &clap::ArgMatches) -> Result<i32, Box<Error>> {
+    let db = JDB::open(conf)?;
+    let uuid_string = value_t!(matches, "uuid", String).unwrap();
+    let uuid = Uuid::parse_str(uuid_string.as_str()).unwrap();
+    db.print_history(
+        &uuid,
+        matches.is_present("headerless"),
+        matches.is_present("parsable"),
+    )
+}
+
+fn diff(conf: &Config, matches: &clap::ArgMatches) -> Result<i32, Box<Error>> {
+    let db = JDB::open(conf)?;
+    let uuid_string = value_t!(matches, "uuid", String).unwrap();
+    let uuid = Uuid::parse_str(uuid_string.as_str()).unwrap();
+    let rev = value_t!(matches, "rev", u64)?;
+    let revision = db.revision(&uuid, rev)?;
+    let jail = db.get(&uuid)?;
+    print_changes(jail.config.diff(&revision.config));
+    Ok(0)
+}
+
+fn rollback_config(conf: &Config, matches: &clap::ArgMatches) -> Result<i32, Box<Error>> {
+    let db = JDB::open(conf)?;
+    let uuid_string = value_t!(matches, "uuid", String).unwrap();
+    let uuid = Uuid::parse_str(uuid_string.as_str()).unwrap();
+    let rev = value_t!(matches, "rev", u64)?;
+    let revision = db.revision(&uuid, rev)?;
+    let Jail { config: old, .. } = db.get(&uuid)?;
+    debug!("Rolling back config"; "vm" => uuid.hyphenated().to_string(), "rev" => rev);
+    let mut c = revision.config;
+    c.normalize();
+    save_update(conf, &old, c)
+}
+
 fn create(conf: &Config, matches: &clap::ArgMatches) -> Result<i32, Box<Error>> {
-    let jail = match value_t!(matches, "file", String) {
+    let mut jail = match value_t!(matches, "file", String) {
         Err(_) => {
             debug!("Reading from STDIN");
             jail_config::JailConfig::from_reader(conf, io::stdin())?
@@ -362,6 +567,14 @@ fn create(conf: &Config, matches: &clap::ArgMatches) -> Result<i32, Box<Error>>
             jail_config::JailConfig::from_reader(conf, File::open(file)?)?
         }
     };
+    {
+        let db = JDB::open(conf)?;
+        db.assign_macs(&mut jail)?;
+        let conflicts = db.conflicts(&jail);
+        if !conflicts.is_empty() {
+            return Err(ValidationErrors::bx(conflicts));
+        }
+    }
     let mut dataset = conf.settings.pool.clone();
     dataset.push('/');
     dataset.push_str(jail.image_uuid.hyphenated().to_string().as_str());
@@ -467,6 +680,18 @@ fn create(conf: &Config, matches: &clap::ArgMatches) -> Result<i32, Box<Error>>
             None => Err(Failure::new(state, GenericError::bx("No snap to clone"))),
         }
     }
+    // pre-create hooks run once the root is in place, a failing
+    // hook rolls back the whole create
+    fn hook_up(state: CreateState) -> Result<CreateState, Failure<CreateState>> {
+        match hooks::run(state.conf, "pre-create", &state.config) {
+            Ok(_) => Ok(state),
+            Err(error) => Err(Failure::new(state, error)),
+        }
+    }
+    fn hook_down(state: CreateState) -> CreateState {
+        state
+    }
+
     fn clone_down(state: CreateState) -> CreateState {
         crit!("Rolling back clone");
         match state.root.clone() {
@@ -481,11 +706,12 @@ fn create(conf: &Config, matches: &clap::ArgMatches) -> Result<i32, Box<Error>>
         Adventure::new(insert_up, insert_down),
         Adventure::new(snap_up, snap_down),
         Adventure::new(clone_up, clone_down),
+        Adventure::new(hook_up, hook_down),
     ]);
     match saga.tell(state) {
         Ok(state) => {
             println!("Created jail {}", state.uuid);
-            Ok(0)
+            hooks::run(conf, "post-create", &state.config)
         }
         Err(failure) => Err(failure.to_error()),
     }
@@ -498,9 +724,10 @@ fn delete(conf: &Config, matches: &clap::ArgMatches) -> Result<i32, Box<Error>>
     debug!("deleteing jail {}", uuid.hyphenated());
     let res = match db.get(&uuid) {
         Ok(jail) => {
+            hooks::run(conf, "pre-delete", &jail.config)?;
             if jail.outer.is_some() {
                 println!("Stopping jail {}", uuid);
-                jail.stop()?;
+                jail.stop(conf)?;
             };
             let origin = zfs::origin(jail.idx.root.as_str());
             match zfs::destroy(jail.idx.root.as_str()) {
@@ -514,25 +741,85 @@ fn delete(conf: &Config, matches: &clap::ArgMatches) -> Result<i32, Box<Error>>
                 }
                 Err(e) => warn!("failed to delete origin: {}", e),
             };
+            let _ = fs::remove_file(jails::jail_conf_path(conf, &uuid));
             println!("deleted jail {}", uuid);
-            Ok(0)
+            Ok(jail.config.clone())
         }
         Err(e) => Err(e),
     };
     db.remove(&uuid)?;
-    res
+    hooks::run(conf, "post-delete", &res?)
+}
+
+fn validate(conf: &Config, matches: &clap::ArgMatches) -> Result<i32, Box<Error>> {
+        match matches.subcommand() {
+            ("create", Some(create_matches)) => validate_create(conf, create_matches),
+            ("update", Some(update_matches)) => validate_update(conf, update_matches),
+            _ => unreachable!(),
+        }
+}
+
+fn validate_create(conf: &Config, matches: &clap::ArgMatches) -> Result<i32, Box<Error>> {
+    let result = match value_t!(matches, "file", String) {
+        Err(_) => {
+            debug!("Reading from STDIN");
+            jail_config::JailConfig::from_reader(conf, io::stdin())
+        }
+        Ok(file) => {
+            debug!("Reading from file"; "file" => file.clone() );
+            jail_config::JailConfig::from_reader(conf, File::open(file)?)
+        }
+    };
+    print_validation(result.err(), matches.is_present("json"))
+}
+
+fn validate_update(conf: &Config, matches: &clap::ArgMatches) -> Result<i32, Box<Error>> {
+    let result = match value_t!(matches, "file", String) {
+        Err(_) => {
+            debug!("Reading from STDIN");
+            update::JailUpdate::from_reader(conf, io::stdin())
+        }
+        Ok(file) => {
+            debug!("Reading from file"; "file" => file.clone() );
+            update::JailUpdate::from_reader(conf, File::open(file)?)
+        }
+    };
+    let error = match result {
+        Err(e) => Some(e),
+        Ok(update) => update.errors(conf).map(ValidationErrors::bx),
+    };
+    print_validation(error, matches.is_present("json"))
+}
+
+/// Prints the errors found validating a payload, payloads that can
+/// not be parsed at all are reported as a single error.
+fn print_validation(error: Option<Box<Error>>, json: bool) -> Result<i32, Box<Error>> {
+    let errors = match error {
+        None => Vec::new(),
+        Some(e) => match e.downcast_ref::<ValidationErrors>() {
+            Some(validation) => validation.errors().clone(),
+            None => vec![ValidationError::new("payload", format!("{}", e).as_str())],
+        },
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&errors)?);
+    } else if errors.is_empty() {
+        println!("Valid payload");
+    } else {
+        for e in errors.iter() {
+            println!("{}", e);
+        }
+    }
+    if errors.is_empty() { Ok(0) } else { Ok(1) }
 }
 
 fn images(conf: &Config, matches: &clap::ArgMatches) -> Result<i32, Box<Error>> {
         match matches.subcommand() {
-            ("avail", Some(avail_matches)) => images_avail(&conf, avail_matches),
-            ("list", Some(list_matches)) => images_list(&conf, list_matches),
-            ("get", Some(get_matches)) => images_get(&conf, get_matches),
-            ("show", Some(show_matches)) => images_show(&conf, show_matches),
-            ("import", Some(import_matches)) => images_import(&conf, import_matches),
-            ("", None) => {
-                Ok(0)
-            }
+            ("avail", Some(avail_matches)) => images_avail(conf, avail_matches),
+            ("list", Some(list_matches)) => images_list(conf, list_matches),
+            ("get", Some(get_matches)) => images_get(conf, get_matches),
+            ("show", Some(show_matches)) => images_show(conf, show_matches),
+            ("import", Some(import_matches)) => images_import(conf, import_matches),
             _ => unreachable!(),
         }
 }
@@ -562,3 +849,33 @@ fn images_import(conf: &Config, matches: &clap::ArgMatches) -> Result<i32, Box<E
     let uuid = Uuid::parse_str(uuid_string.as_str()).unwrap();
     images::import(conf, uuid)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exec_args_plain() {
+        let args = exec_args(7, None, &[], &["ls", "-l"]).unwrap();
+        assert_eq!(args, vec!["7", "ls", "-l"]);
+    }
+
+    #[test]
+    fn exec_args_user_and_env() {
+        let args = exec_args(7, Some("www"), &["A=1", "B=x=y"], &["ls"]).unwrap();
+        assert_eq!(args, vec!["-U", "www", "7", "env", "A=1", "B=x=y", "ls"]);
+    }
+
+    #[test]
+    fn exec_args_bad_env() {
+        assert!(exec_args(7, None, &["A"], &["ls"]).is_err());
+        assert!(exec_args(7, None, &["=1"], &["ls"]).is_err());
+    }
+
+    #[test]
+    fn exit_codes() {
+        assert_eq!(exit_code(Some(0), None), 0);
+        assert_eq!(exit_code(Some(3), None), 3);
+        assert_eq!(exit_code(None, Some(9)), 137);
+    }
+}
@@ -1,7 +1,17 @@
 //! Update for a jail
-use jail_config::{JailConfig, NIC};
+use jail_config::{JailConfig, JailParams, NIC, Threshold, route_errors, nic_ref};
+use jail_config::{unknown_keys, unknown_nic_keys, ignored_keys};
+use jail_config::{interface_error, mac_error, ip_error, netmask_error, gateway_error};
+use jail_config::{ip6_error, gateway6_error, ips_error, gateways_error, list_errors};
+use jail_config::{nic_tag_error, vlan_error, mtu_error, cpuset_error, cpuset_from_ids};
+use jail_config::{devfs_error, command_error, env_errors, rename_alias};
+use jail_config::{HOSTNAME_RE, ALIAS_RE, INTERFACE_RE, MAC_RE};
+use config::Config;
+use hooks;
+use errors::{ValidationError, ValidationErrors};
 use std::error::Error;
 use std::io::Read;
+use std::collections::BTreeMap as Map;
 use serde_json;
 use uuid::Uuid;
 
@@ -28,14 +38,19 @@ macro_rules! update_option {
 }
 
 
-/// update the nics
+/// update the nics, the nic is selected by its mac or interface
 #[derive(Debug, Deserialize, Clone)]
 struct NICUpdate {
-    mac: String,
+    mac: Option<String>,
+    interface: Option<String>,
     nic_tag: Option<String>,
     ip: Option<String>,
     gateway: Option<String>,
     netmask: Option<String>,
+    ip6: Option<String>,
+    gateway6: Option<String>,
+    ips: Option<Vec<String>>,
+    gateways: Option<Vec<String>>,
     vlan: Option<u16>,
     primary: Option<bool>,
     mtu: Option<u32>,
@@ -46,40 +61,189 @@ impl NICUpdate {
     #[cfg(test)]
     pub fn empty(mac: String) -> Self {
         NICUpdate{
-            mac,
+            mac: Some(mac),
+            interface: None,
             nic_tag: None,
             ip: None,
             gateway: None,
             netmask: None,
+            ip6: None,
+            gateway6: None,
+            ips: None,
+            gateways: None,
             vlan: None,
             primary: None,
             mtu: None,
             network_uuid: None,
         }
     }
+    /// Checks if the update selects the given nic
+    pub fn selects(&self, nic: &NIC) -> bool {
+        let mac = match self.mac {
+            Some(ref mac) => nic.mac.eq_ignore_ascii_case(mac),
+            None => true,
+        };
+        let interface = match self.interface {
+            Some(ref interface) => nic.interface == *interface,
+            None => true,
+        };
+        (self.mac.is_some() || self.interface.is_some()) && mac && interface
+    }
+
     pub fn apply(&self, nic: NIC) -> NIC {
 
-        if nic.mac != self.mac {
+        if !self.selects(&nic) {
             return nic
         };
 
         let mut nic = nic.clone();
+        // Setting the address lists replaces the single address fields
+        // and the other way around, `JailConfig::normalize` brings
+        // them back in line.
+        if self.ips.is_some() {
+            nic.ip = String::new();
+            nic.netmask = None;
+            nic.ip6 = None;
+        } else if self.ip.is_some() || self.netmask.is_some() || self.ip6.is_some() {
+            nic.ips = Vec::new();
+        }
+        if self.gateways.is_some() {
+            nic.gateway = None;
+            nic.gateway6 = None;
+        } else if self.gateway.is_some() || self.gateway6.is_some() {
+            nic.gateways = Vec::new();
+        }
         update!(self, nic;
                 nic_tag,
                 ip,
-                netmask,
-                gateway,
+                ips,
+                gateways,
                 primary
         );
         update_option!(self, nic;
+                       netmask,
+                       gateway,
+                       ip6,
+                       gateway6,
                        vlan,
                        mtu,
                        network_uuid
         );
         return nic;
     }
+
+    /// checks the fields set by the update for errors, `path` is the
+    /// path of the update in the payload (like `update_nics.0`).
+    pub fn errors(&self, config: &Config, path: &str) -> Vec<ValidationError> {
+        let mut errors = Vec::new();
+        let field = |name: &str| format!("{}.{}", path, name);
+        match (&self.mac, &self.interface) {
+            (&None, &None) => {
+                errors.push(ValidationError::new(path, "Either mac or interface is required"))
+            }
+            (&Some(ref mac), _) => {
+                if let Some(error) = mac_error(mac.as_str()) {
+                    errors.push(ValidationError::new(field("mac").as_str(), error))
+                }
+            }
+            (_, &Some(ref interface)) => {
+                if let Some(error) = interface_error(interface.as_str()) {
+                    errors.push(ValidationError::new(field("interface").as_str(), error))
+                }
+            }
+        }
+        for &(name, ref value, check) in [
+            ("ip", &self.ip, ip_error as fn(&str) -> Option<&'static str>),
+            ("netmask", &self.netmask, netmask_error),
+            ("gateway", &self.gateway, gateway_error),
+            ("ip6", &self.ip6, ip6_error),
+            ("gateway6", &self.gateway6, gateway6_error),
+        ].iter()
+        {
+            if let Some(error) = value.as_ref().and_then(|value| check(value.as_str())) {
+                errors.push(ValidationError::new(field(name).as_str(), error))
+            }
+        }
+        if let Some(ref ips) = self.ips {
+            errors.append(&mut list_errors(field("ips").as_str(), ips, ips_error));
+        }
+        if let Some(ref gateways) = self.gateways {
+            errors.append(&mut list_errors(
+                field("gateways").as_str(),
+                gateways,
+                gateways_error,
+            ));
+        }
+        if let Some(error) = self.nic_tag.as_ref().and_then(|tag| nic_tag_error(config, tag)) {
+            errors.push(ValidationError::new(field("nic_tag").as_str(), error))
+        }
+        if let Some(error) = self.vlan.and_then(vlan_error) {
+            errors.push(ValidationError::new(field("vlan").as_str(), error))
+        }
+        if let Some(error) = self.mtu.and_then(mtu_error) {
+            errors.push(ValidationError::new(field("mtu").as_str(), error))
+        }
+        errors
+    }
 }
 
+/// Optional limits an update can remove
+static LIMIT_KEYS: &'static [&'static str] = &[
+    "max_swap",
+    "max_vmemory",
+    "max_open_files",
+    "max_read_bps",
+    "max_write_bps",
+    "max_read_iops",
+    "max_write_iops",
+    "max_msg_ids",
+    "max_sem_ids",
+    "max_shm_ids",
+];
+
+/// Properties an update can have
+static UPDATE_KEYS: &'static [&'static str] = &[
+    "alias",
+    "hostname",
+    "autostart",
+    "max_physical_memory",
+    "cpu_cap",
+    "quota",
+    "max_shm_memory",
+    "max_locked_memory",
+    "max_lwps",
+    "thresholds",
+    "cpuset",
+    "jail_params",
+    "devfs",
+    "init_name",
+    "stop_command",
+    "exec_env",
+    "hooks",
+    "max_swap",
+    "max_vmemory",
+    "max_open_files",
+    "max_read_bps",
+    "max_write_bps",
+    "max_read_iops",
+    "max_write_iops",
+    "max_msg_ids",
+    "max_sem_ids",
+    "max_shm_ids",
+    "archive_on_delete",
+    "billing_id",
+    "do_not_inventory",
+    "dns_domain",
+    "owner_uuid",
+    "package_name",
+    "package_version",
+    "add_nics",
+    "remove_nics",
+    "update_nics",
+    "set_routes",
+    "remove_routes",
+];
+
 /// Jail update
 #[derive(Debug, Deserialize, Clone)]
 pub struct JailUpdate {
@@ -91,18 +255,59 @@ pub struct JailUpdate {
     autostart: Option<bool>,
     /// max physical memory in MB (memoryuse)
     max_physical_memory: Option<u64>,
-    /// mac cpu usage 100 = 1 core (pcpu)
+    /// max cpu usage in percent, 100 = 1 core and 0 for no limit (pcpu)
     cpu_cap: Option<u64>,
-    /// max quota (zfs quota)
-    //    quota: u64,
-    /// SysV shared memory size, in bytes (shmsize)
+    /// max quota in GB (zfs quota)
+    quota: Option<u64>,
+    /// SysV shared memory size in MB, defaults to max_physical_memory
+    /// (shmsize)
     max_shm_memory: Option<u64>,
 
-    /// locked memory (memorylocked)
+    /// locked memory in MB, defaults to max_physical_memory
+    /// (memorylocked)
     max_locked_memory: Option<u64>,
 
     /// maximum number of porocesses (maxproc)
     max_lwps: Option<u64>,
+    /// cpus the jail is pinned to, an empty string or list removes the
+    /// pinning
+    cpuset: Option<String>,
+    /// rctl thresholds, replaces all thresholds of the jail
+    thresholds: Option<Map<String, Vec<Threshold>>>,
+    /// jail(8) parameters, replaces all parameters of the jail
+    jail_params: Option<JailParams>,
+    /// device patterns to unhide, replaces all patterns of the jail
+    devfs: Option<Vec<String>>,
+    /// start command of the inner jail, an empty string restores the
+    /// default
+    init_name: Option<String>,
+    /// stop command of the inner jail, an empty string removes it
+    stop_command: Option<String>,
+    /// environment of the start and stop command, replaces the whole
+    /// environment
+    exec_env: Option<Map<String, String>>,
+    /// lifecycle hooks, replaces all hooks of the jail
+    hooks: Option<Map<String, Vec<String>>>,
+    /// max swap in MB (swapuse)
+    max_swap: Option<u64>,
+    /// max virtual memory in MB (vmemoryuse)
+    max_vmemory: Option<u64>,
+    /// maximum number of open files (openfiles)
+    max_open_files: Option<u64>,
+    /// disk read bytes per second (readbps)
+    max_read_bps: Option<u64>,
+    /// disk write bytes per second (writebps)
+    max_write_bps: Option<u64>,
+    /// disk read operations per second (readiops)
+    max_read_iops: Option<u64>,
+    /// disk write operations per second (writeiops)
+    max_write_iops: Option<u64>,
+    /// maximum number of SysV message queues (nmsgq)
+    max_msg_ids: Option<u64>,
+    /// maximum number of SysV semaphores (nsem)
+    max_sem_ids: Option<u64>,
+    /// maximum number of SysV shared memory segments (nshm)
+    max_shm_ids: Option<u64>,
 
     // Metadata fields w/o effect on vmadm at the moment
     archive_on_delete: Option<bool>,
@@ -120,16 +325,67 @@ pub struct JailUpdate {
     remove_nics: Vec<String>,
     #[serde(default = "empty_nic_update")]
     update_nics: Vec<NICUpdate>,
+    #[serde(default = "empty_routes")]
+    set_routes: Map<String, String>,
+    #[serde(default = "empty_routes_list")]
+    remove_routes: Vec<String>,
 
 }
 
 impl JailUpdate {
     /// Reads the config from a reader
-    pub fn from_reader<R>(reader: R) -> Result<Self, Box<Error>>
+    pub fn from_reader<R>(config: &Config, reader: R) -> Result<Self, Box<Error>>
     where
         R: Read,
     {
-        let update: JailUpdate = serde_json::from_reader(reader)?;
+        let value: serde_json::Value = serde_json::from_reader(reader)?;
+        JailUpdate::from_value(config, value)
+    }
+
+    /// Reads the update from `key=value` arguments, values are coerced
+    /// to the type of the field, lists and objects are given as JSON.
+    pub fn from_args(config: &Config, args: Vec<String>) -> Result<Self, Box<Error>> {
+        let mut object = serde_json::Map::new();
+        let mut errors = Vec::new();
+        for arg in args.iter() {
+            let (key, value) = match arg.find('=') {
+                Some(idx) => (&arg[..idx], &arg[idx + 1..]),
+                None => {
+                    errors.push(ValidationError::new(arg.as_str(), "Expected key=value"));
+                    continue;
+                }
+            };
+            match coerce(key, value) {
+                Ok(value) => {
+                    object.insert(String::from(key), value);
+                }
+                Err(error) => errors.push(ValidationError::new(key, error)),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(ValidationErrors::bx(errors));
+        }
+        JailUpdate::from_value(config, serde_json::Value::Object(object))
+    }
+
+    fn from_value(config: &Config, mut value: serde_json::Value) -> Result<Self, Box<Error>> {
+        // `null` removes a limit just like `0` does
+        if let Some(object) = value.as_object_mut() {
+            for key in LIMIT_KEYS.iter() {
+                if object.get(*key).map_or(false, |v| v.is_null()) {
+                    object.insert(String::from(*key), serde_json::Value::from(0));
+                }
+            }
+        }
+        let mut unknown = cpuset_from_ids(&mut value);
+        unknown.extend(rename_alias(&mut value, "boot_command", "init_name"));
+        unknown.extend(unknown_nic_keys(&value, "add_nics"));
+        unknown.extend(unknown_nic_keys(&value, "update_nics"));
+        unknown.extend(ignored_keys(config, unknown_keys(&value, UPDATE_KEYS, "")));
+        if !unknown.is_empty() {
+            return Err(ValidationErrors::bx(unknown));
+        }
+        let update: JailUpdate = serde_json::from_value(value)?;
         return Ok(update);
     }
     #[cfg(test)]
@@ -140,9 +396,28 @@ impl JailUpdate {
             autostart: None,
             max_physical_memory: None,
             cpu_cap: None,
+            quota: None,
             max_shm_memory: None,
             max_locked_memory: None,
             max_lwps: None,
+            thresholds: None,
+            cpuset: None,
+            jail_params: None,
+            devfs: None,
+            init_name: None,
+            stop_command: None,
+            exec_env: None,
+            hooks: None,
+            max_swap: None,
+            max_vmemory: None,
+            max_open_files: None,
+            max_read_bps: None,
+            max_write_bps: None,
+            max_read_iops: None,
+            max_write_iops: None,
+            max_msg_ids: None,
+            max_sem_ids: None,
+            max_shm_ids: None,
             archive_on_delete: None,
             billing_id: None,
             do_not_inventory: None,
@@ -153,9 +428,143 @@ impl JailUpdate {
             add_nics: vec![],
             remove_nics: vec![],
             update_nics: vec![],
+            set_routes: Map::new(),
+            remove_routes: vec![],
+        }
+    }
+    /// Checks the update for errors that can be found without knowing
+    /// the jail it is applied to.
+    pub fn errors(&self, config: &Config) -> Option<Vec<ValidationError>> {
+        let mut errors = Vec::new();
+        if let Some(ref hostname) = self.hostname {
+            if !HOSTNAME_RE.is_match(hostname.as_str()) {
+                errors.push(ValidationError::new("hostname", "Invalid hostname"))
+            }
+        }
+        if let Some(ref alias) = self.alias {
+            if !ALIAS_RE.is_match(alias.as_str()) {
+                errors.push(ValidationError::new("alias", "Invalid alias"))
+            }
+        }
+        if let Some(ref cpuset) = self.cpuset {
+            if !cpuset.is_empty() {
+                if let Some(error) = cpuset_error(cpuset.as_str(), None) {
+                    errors.push(ValidationError::new("cpuset", error))
+                }
+            }
+        }
+        for &(field, value) in [
+            ("init_name", &self.init_name),
+            ("stop_command", &self.stop_command),
+        ].iter()
+        {
+            if let Some(ref command) = *value {
+                // an empty command resets it
+                if !command.is_empty() {
+                    if let Some(error) = command_error(command.as_str()) {
+                        errors.push(ValidationError::new(field, error))
+                    }
+                }
+            }
+        }
+        if let Some(ref env) = self.exec_env {
+            errors.append(&mut env_errors("exec_env", env));
+        }
+        if let Some(ref list) = self.hooks {
+            errors.append(&mut hooks::errors("hooks", list));
+        }
+        if let Some(ref devfs) = self.devfs {
+            for (i, pattern) in devfs.iter().enumerate() {
+                if let Some(error) = devfs_error(pattern.as_str()) {
+                    errors.push(ValidationError::new(format!("devfs.{}", i).as_str(), error))
+                }
+            }
+        }
+        if let Some(ref params) = self.jail_params {
+            errors.append(&mut params.errors("jail_params"));
+        }
+        for (i, nic) in self.add_nics.iter().enumerate() {
+            let mut nic = nic.clone();
+            nic.normalize();
+            errors.append(&mut nic.errors(config, format!("add_nics.{}", i).as_str()));
+        }
+        for (i, selector) in self.remove_nics.iter().enumerate() {
+            if !MAC_RE.is_match(selector.as_str()) && !INTERFACE_RE.is_match(selector.as_str()) {
+                errors.push(ValidationError::new(
+                    format!("remove_nics.{}", i).as_str(),
+                    "Invalid mac or interface",
+                ))
+            }
+        }
+        for (i, update) in self.update_nics.iter().enumerate() {
+            errors.append(&mut update.errors(config, format!("update_nics.{}", i).as_str()));
+        }
+        for (dst, gateway) in self.set_routes.iter() {
+            errors.append(&mut route_errors(
+                format!("set_routes.{}", dst).as_str(),
+                dst,
+                gateway,
+                None,
+            ));
+        }
+        if errors.is_empty() {
+            None
+        } else {
+            Some(errors)
+        }
+    }
 
+    /// Checks that every nic selector in `remove_nics` and
+    /// `update_nics` matches exactly one nic of the config the update
+    /// is applied to.
+    pub fn selector_errors(&self, config: &JailConfig) -> Option<Vec<ValidationError>> {
+        let mut errors = Vec::new();
+        for (i, selector) in self.remove_nics.iter().enumerate() {
+            let count = config.nics.iter().filter(|nic| removes(selector, nic)).count();
+            if let Some(error) = selector_error(count) {
+                errors.push(ValidationError::new(format!("remove_nics.{}", i).as_str(), error))
+            }
+        }
+        for (dst, gateway) in config.routes.iter() {
+            if self.remove_routes.contains(dst) || self.set_routes.contains_key(dst) {
+                continue;
+            }
+            let removed = nic_ref(gateway)
+                .and_then(|idx| config.nics.get(idx))
+                .map_or(false, |nic| self.remove_nics.iter().any(|selector| removes(selector, nic)));
+            if removed {
+                errors.push(ValidationError::new(
+                    format!("routes.{}", dst).as_str(),
+                    "Route points at a removed nic",
+                ))
+            }
         }
+        // updates are applied after nics got removed and added
+        let nics = self.nics_after_remove_and_add(config);
+        for (i, update) in self.update_nics.iter().enumerate() {
+            let count = nics.iter().filter(|nic| update.selects(nic)).count();
+            if let Some(error) = selector_error(count) {
+                errors.push(ValidationError::new(format!("update_nics.{}", i).as_str(), error))
+            }
+        }
+        if errors.is_empty() {
+            None
+        } else {
+            Some(errors)
+        }
+    }
+
+    fn nics_after_remove_and_add(&self, config: &JailConfig) -> Vec<NIC> {
+        let mut nics: Vec<NIC> = config
+            .nics
+            .iter()
+            .filter(|nic| !self.remove_nics.iter().any(|selector| removes(selector, nic)))
+            .cloned()
+            .collect();
+        nics.extend(self.add_nics.iter().cloned());
+        nics
     }
+
     pub fn apply(&self, config: JailConfig) -> JailConfig {
         let mut c = config.clone();
         update!(self, c;
@@ -164,7 +573,13 @@ impl JailUpdate {
             hostname,
             max_physical_memory,
             cpu_cap,
+            quota,
             max_lwps,
+            thresholds,
+            jail_params,
+            devfs,
+            exec_env,
+            hooks,
             dns_domain
         );
         update_option!(self, c;
@@ -179,10 +594,33 @@ impl JailUpdate {
         );
 
 
-        c.nics.retain(|nic| !self.remove_nics.contains(&nic.mac));
-        for nic in self.add_nics.iter() {
-            c.nics.push(nic.clone());
-        }
+        set_or_clear_limit(&self.max_swap, &mut c.max_swap);
+        set_or_clear_limit(&self.max_vmemory, &mut c.max_vmemory);
+        set_or_clear_limit(&self.max_open_files, &mut c.max_open_files);
+        set_or_clear_limit(&self.max_read_bps, &mut c.max_read_bps);
+        set_or_clear_limit(&self.max_write_bps, &mut c.max_write_bps);
+        set_or_clear_limit(&self.max_read_iops, &mut c.max_read_iops);
+        set_or_clear_limit(&self.max_write_iops, &mut c.max_write_iops);
+        set_or_clear_limit(&self.max_msg_ids, &mut c.max_msg_ids);
+        set_or_clear_limit(&self.max_sem_ids, &mut c.max_sem_ids);
+        set_or_clear_limit(&self.max_shm_ids, &mut c.max_shm_ids);
+        set_or_clear(&self.cpuset, &mut c.cpuset);
+        set_or_clear(&self.init_name, &mut c.init_name);
+        set_or_clear(&self.stop_command, &mut c.stop_command);
+
+        let nics = self.nics_after_remove_and_add(&c);
+        // existing `nics[<idx>]` routes follow their nic to its new index
+        c.routes = c.routes
+            .iter()
+            .filter_map(|(dst, gateway)| match nic_ref(gateway) {
+                Some(idx) => c.nics
+                    .get(idx)
+                    .and_then(|old| nics.iter().position(|nic| nic.mac == old.mac))
+                    .map(|idx| (dst.clone(), format!("nics[{}]", idx))),
+                None => Some((dst.clone(), gateway.clone())),
+            })
+            .collect();
+        c.nics = nics;
         for update in self.update_nics.iter() {
 
             c.nics = match update.primary {
@@ -199,16 +637,90 @@ impl JailUpdate {
 
         }
 
+        for dst in self.remove_routes.iter() {
+            c.routes.remove(dst);
+        }
+        for (dst, gateway) in self.set_routes.iter() {
+            c.routes.insert(dst.clone(), gateway.clone());
+        }
         return c;
     }
 }
 
 
 
+/// Sets an optional string property, an empty string removes it
+fn set_or_clear(value: &Option<String>, target: &mut Option<String>) {
+    if let Some(ref value) = *value {
+        *target = if value.is_empty() {
+            None
+        } else {
+            Some(value.clone())
+        };
+    }
+}
+
+/// Sets an optional limit, `0` removes it
+fn set_or_clear_limit(value: &Option<u64>, target: &mut Option<u64>) {
+    match *value {
+        Some(0) => *target = None,
+        Some(value) => *target = Some(value),
+        None => (),
+    }
+}
+
+/// Checks if a `remove_nics` entry, a mac or interface, selects a nic
+fn removes(selector: &str, nic: &NIC) -> bool {
+    nic.mac.eq_ignore_ascii_case(selector) || nic.interface == selector
+}
+
+fn selector_error(count: usize) -> Option<&'static str> {
+    match count {
+        0 => Some("No nic matches"),
+        1 => None,
+        _ => Some("More than one nic matches"),
+    }
+}
+
+/// Converts a command line value to the JSON type of the field.
+fn coerce(key: &str, value: &str) -> Result<serde_json::Value, &'static str> {
+    match key {
+        "autostart" | "archive_on_delete" | "do_not_inventory" => {
+            match value {
+                "true" => Ok(serde_json::Value::Bool(true)),
+                "false" => Ok(serde_json::Value::Bool(false)),
+                _ => Err("Invalid boolean"),
+            }
+        }
+        "max_physical_memory" | "cpu_cap" | "quota" | "max_shm_memory" | "max_locked_memory" |
+        "max_lwps" | "max_swap" | "max_vmemory" | "max_open_files" | "max_read_bps" |
+        "max_write_bps" | "max_read_iops" | "max_write_iops" | "max_msg_ids" |
+        "max_sem_ids" | "max_shm_ids" => {
+            match value.parse::<u64>() {
+                Ok(n) => Ok(serde_json::Value::from(n)),
+                Err(_) => Err("Invalid number"),
+            }
+        }
+        "add_nics" | "remove_nics" | "update_nics" | "set_routes" | "remove_routes" |
+        "thresholds" | "jail_params" | "devfs" | "exec_env" | "hooks" => {
+            serde_json::from_str(value).map_err(|_| "Invalid JSON")
+        }
+        _ => Ok(serde_json::Value::String(String::from(value))),
+    }
+}
+
 fn empty_macs() -> Vec<String> {
     Vec::new()
 }
 
+fn empty_routes_list() -> Vec<String> {
+    Vec::new()
+}
+
+fn empty_routes() -> Map<String, String> {
+    Map::new()
+}
+
 fn empty_nic_update() -> Vec<NICUpdate> {
     Vec::new()
 }
@@ -222,9 +734,33 @@ fn empty_nics() -> Vec<NIC> {
 #[cfg(test)]
 mod tests {
     use jail_config::JailConfig;
+    use config::{Config, Settings};
     use update::*;
     use uuid::Uuid;
 
+    fn config() -> Config {
+        let mut networks = Map::new();
+        networks.insert(String::from("admin"), String::from("bridge0"));
+        Config {
+            settings: Settings {
+                pool: String::from("zroot/jails"),
+                repo: String::from("https://bsd.project-fifo.net/images"),
+                conf_dir: String::from("/etc/jails"),
+                image_dir: String::from("/var/imgadm/images"),
+                devfs_ruleset: 4,
+                devfs_ruleset_base: 1000,
+                mac_oui: String::from("02"),
+                strict: false,
+                history_size: 10,
+                thresholds: Map::new(),
+                jail_params: JailParams::new(),
+                hooks: Map::new(),
+                networks: networks,
+            },
+            req_id: String::from("test"),
+        }
+    }
+
     fn nic00() -> NIC {
         NIC{
             interface: String::from("net0"),
@@ -232,8 +768,12 @@ mod tests {
             vlan: None,
             nic_tag: String::from("admin"),
             ip: String::from("192.168.254.254"),
-            netmask: String::from("255.255.255.0"),
-            gateway: String::from("192.168.254.1"),
+            netmask: Some(String::from("255.255.255.0")),
+            gateway: Some(String::from("192.168.254.1")),
+            ip6: None,
+            gateway6: None,
+            ips: vec![],
+            gateways: vec![],
             primary: true,
             mtu: None,
             network_uuid: None
@@ -246,8 +786,12 @@ mod tests {
             vlan: None,
             nic_tag: String::from("admin"),
             ip: String::from("192.168.254.253"),
-            netmask: String::from("255.255.255.0"),
-            gateway: String::from("192.168.254.1"),
+            netmask: Some(String::from("255.255.255.0")),
+            gateway: Some(String::from("192.168.254.1")),
+            ip6: None,
+            gateway6: None,
+            ips: vec![],
+            gateways: vec![],
             primary: false,
             mtu: None,
             network_uuid: None
@@ -260,8 +804,12 @@ mod tests {
             vlan: None,
             nic_tag: String::from("admin"),
             ip: String::from("192.168.254.252"),
-            netmask: String::from("255.255.255.0"),
-            gateway: String::from("192.168.254.1"),
+            netmask: Some(String::from("255.255.255.0")),
+            gateway: Some(String::from("192.168.254.1")),
+            ip6: None,
+            gateway6: None,
+            ips: vec![],
+            gateways: vec![],
             primary: false,
             mtu: None,
             network_uuid: None
@@ -282,7 +830,26 @@ mod tests {
             max_shm_memory: None,
             max_locked_memory: None,
             nics: vec![nic00(), nic01()],
+            routes: Map::new(),
             max_lwps: 2000,
+            thresholds: Map::new(),
+            cpuset: None,
+            jail_params: JailParams::new(),
+            devfs: vec![],
+            init_name: None,
+            stop_command: None,
+            exec_env: Map::new(),
+            hooks: Map::new(),
+            max_swap: None,
+            max_vmemory: None,
+            max_open_files: None,
+            max_read_bps: None,
+            max_write_bps: None,
+            max_read_iops: None,
+            max_write_iops: None,
+            max_msg_ids: None,
+            max_sem_ids: None,
+            max_shm_ids: None,
             archive_on_delete: None,
             billing_id: None,
             do_not_inventory: None,
@@ -428,6 +995,41 @@ mod tests {
         assert_eq!(vec![nic00(), nic01(), nic02()], update.apply(conf).nics);
     }
 
+    #[test]
+    fn set_routes() {
+        let conf = conf();
+        let mut update = JailUpdate::empty();
+        update.set_routes.insert(String::from("10.0.0.0/8"), String::from("192.168.254.1"));
+        update.set_routes.insert(String::from("10.1.0.1"), String::from("nics[1]"));
+        let conf1 = update.apply(conf);
+        assert_eq!(2, conf1.routes.len());
+        assert_eq!("nics[1]", conf1.routes["10.1.0.1"]);
+    }
+    #[test]
+    fn remove_routes() {
+        let mut conf = conf();
+        conf.routes.insert(String::from("10.0.0.0/8"), String::from("192.168.254.1"));
+        conf.routes.insert(String::from("10.1.0.1"), String::from("nics[1]"));
+        let mut update = JailUpdate::empty();
+        update.remove_routes = vec![String::from("10.0.0.0/8")];
+        let conf1 = update.apply(conf);
+        assert_eq!(vec!["10.1.0.1"], conf1.routes.keys().collect::<Vec<&String>>());
+    }
+    #[test]
+    fn routes_follow_nics() {
+        let mut conf = conf();
+        conf.nics.push(nic02());
+        conf.routes.insert(String::from("10.1.0.1"), String::from("nics[2]"));
+        let mut update = JailUpdate::empty();
+        update.remove_nics = vec![nic01().mac.clone()];
+        let conf1 = update.clone().apply(conf.clone());
+        assert_eq!("nics[1]", conf1.routes["10.1.0.1"]);
+        conf.routes.insert(String::from("10.2.0.1"), String::from("nics[1]"));
+        assert!(update.selector_errors(&conf).is_some());
+        update.remove_routes = vec![String::from("10.2.0.1")];
+        assert!(update.selector_errors(&conf).is_none());
+    }
+
     #[test]
     fn nics_change_primary() {
         let conf = conf();
@@ -475,7 +1077,7 @@ mod tests {
         let mut update = NICUpdate::empty(nic.mac.clone());
         let gateway = String::from("192.168.1.1");
         update.gateway = Some(gateway.clone());
-        assert_eq!(gateway, update.apply(nic).gateway);
+        assert_eq!(gateway, update.apply(nic).gateway.unwrap());
     }
     #[test]
     fn nic_netmask() {
@@ -483,7 +1085,69 @@ mod tests {
         let mut update = NICUpdate::empty(nic.mac.clone());
         let netmask = String::from("255.255.0.0");
         update.netmask = Some(netmask.clone());
-        assert_eq!(netmask, update.apply(nic).netmask);
+        assert_eq!(netmask, update.apply(nic).netmask.unwrap());
+    }
+    #[test]
+    fn nic_dhcp() {
+        let nic = nic01();
+        let mut update = NICUpdate::empty(nic.mac.clone());
+        let ip = String::from("dhcp");
+        update.ip = Some(ip.clone());
+        let nic = update.apply(nic);
+        assert_eq!(ip, nic.ip);
+        assert!(nic.is_dhcp());
+    }
+    #[test]
+    fn nic_ip6() {
+        let nic = nic01();
+        let mut update = NICUpdate::empty(nic.mac.clone());
+        let ip6 = String::from("fd00::1/64");
+        update.ip6 = Some(ip6.clone());
+        assert_eq!(ip6, update.apply(nic).ip6.unwrap());
+    }
+    #[test]
+    fn nic_gateway6() {
+        let nic = nic01();
+        let mut update = NICUpdate::empty(nic.mac.clone());
+        let gateway6 = String::from("fe80::1");
+        update.gateway6 = Some(gateway6.clone());
+        assert_eq!(gateway6, update.apply(nic).gateway6.unwrap());
+    }
+    #[test]
+    fn nic_ips() {
+        let nic = nic01();
+        let mut update = NICUpdate::empty(nic.mac.clone());
+        update.ips = Some(vec![
+            String::from("10.0.0.5/24"),
+            String::from("10.0.0.6/24"),
+            String::from("FD00:0::5/64"),
+        ]);
+        update.gateways = Some(vec![String::from("10.0.0.1")]);
+        let mut nic = update.apply(nic);
+        nic.normalize();
+        assert_eq!(
+            vec![
+                String::from("10.0.0.5/24"),
+                String::from("10.0.0.6/24"),
+                String::from("fd00::5/64"),
+            ],
+            nic.ips
+        );
+        assert_eq!("10.0.0.5", nic.ip);
+        assert_eq!(Some(String::from("255.255.255.0")), nic.netmask);
+        assert_eq!(Some(String::from("10.0.0.1")), nic.gateway);
+        assert_eq!(Some(String::from("fd00::5/64")), nic.ip6);
+        assert_eq!(None, nic.gateway6);
+    }
+    #[test]
+    fn nic_ip_replaces_ips() {
+        let mut nic = nic01();
+        nic.normalize();
+        let mut update = NICUpdate::empty(nic.mac.clone());
+        update.ip = Some(String::from("192.168.1.254"));
+        let mut nic = update.apply(nic);
+        nic.normalize();
+        assert_eq!(vec![String::from("192.168.1.254/24")], nic.ips);
     }
     #[test]
     fn nic_vlan() {
@@ -509,6 +1173,180 @@ mod tests {
         update.mtu = Some(mtu);
         assert_eq!(mtu, update.apply(nic).mtu.unwrap());
     }
+    #[test]
+    fn errors() {
+        let mut update = JailUpdate::empty();
+        assert!(update.errors(&config()).is_none());
+        let mut nic = nic01();
+        nic.nic_tag = String::from("external");
+        update.add_nics.push(nic);
+        let mut nic_update = NICUpdate::empty(String::from("00:00:00:00:00:00"));
+        nic_update.gateways = Some(vec![String::from("nope")]);
+        nic_update.vlan = Some(5000);
+        update.update_nics.push(nic_update);
+        update.set_routes.insert(String::from("10.0.0.0/8"), String::from("nope"));
+        let fields: Vec<String> = update
+            .errors(&config())
+            .unwrap()
+            .iter()
+            .map(|e| String::from(e.field()))
+            .collect();
+        assert_eq!(
+            vec![
+                String::from("add_nics.0.nic_tag"),
+                String::from("update_nics.0.gateways.0"),
+                String::from("update_nics.0.vlan"),
+                String::from("set_routes.10.0.0.0/8"),
+            ],
+            fields
+        );
+    }
+    #[test]
+    fn merged_errors() {
+        let mut conf = conf();
+        conf.nics[1].interface = String::from("net1");
+        assert!(conf.errors(&config()).is_none());
+        let mut update = JailUpdate::empty();
+        update.hostname = Some(String::from("not valid"));
+        let mut nic_update = NICUpdate::empty(conf.nics[1].mac.clone());
+        nic_update.nic_tag = Some(String::from("external"));
+        update.update_nics.push(nic_update);
+        let fields: Vec<String> = update
+            .apply(conf)
+            .errors(&config())
+            .unwrap()
+            .iter()
+            .map(|e| String::from(e.field()))
+            .collect();
+        assert_eq!(
+            vec![String::from("hostname"), String::from("nics.1.nic_tag")],
+            fields
+        );
+    }
+    #[test]
+    fn from_args() {
+        let args = vec![
+            String::from("alias=foo"),
+            String::from("max_physical_memory=2048"),
+            String::from("autostart=false"),
+            String::from(r#"set_routes={"10.0.0.0/8": "nics[0]"}"#),
+        ];
+        let update = JailUpdate::from_args(&config(), args).unwrap();
+        let conf = update.apply(conf());
+        assert_eq!("foo", conf.alias);
+        assert_eq!(2048, conf.max_physical_memory);
+        assert!(!conf.autostart);
+        assert_eq!(Some(&String::from("nics[0]")), conf.routes.get("10.0.0.0/8"));
+    }
+    #[test]
+    fn from_args_invalid() {
+        let args = vec![
+            String::from("max_physical_memory=lots"),
+            String::from("autostart=yes"),
+            String::from("alias"),
+        ];
+        assert!(JailUpdate::from_args(&config(), args).is_err());
+    }
+    #[test]
+    fn nic_by_interface() {
+        let mut conf = conf();
+        conf.nics[1].interface = String::from("net1");
+        let mut nic_update = NICUpdate::empty(String::new());
+        nic_update.mac = None;
+        nic_update.interface = Some(String::from("net1"));
+        nic_update.mtu = Some(1500);
+        let mut update = JailUpdate::empty();
+        update.update_nics.push(nic_update);
+        assert!(update.selector_errors(&conf).is_none());
+        let conf = update.apply(conf);
+        assert_eq!(None, conf.nics[0].mtu);
+        assert_eq!(Some(1500), conf.nics[1].mtu);
+    }
+    #[test]
+    fn remove_nic_by_interface() {
+        let mut conf = conf();
+        conf.nics[1].interface = String::from("net1");
+        let mut update = JailUpdate::empty();
+        update.remove_nics = vec![String::from("net1")];
+        assert!(update.selector_errors(&conf).is_none());
+        assert_eq!(vec![nic00()], update.apply(conf).nics);
+    }
+    #[test]
+    fn selector_errors() {
+        let conf = conf();
+        let mut update = JailUpdate::empty();
+        update.remove_nics = vec![String::from("net0")];
+        update.update_nics.push(NICUpdate::empty(String::from("00:00:00:00:00:42")));
+        let errors: Vec<String> = update
+            .selector_errors(&conf)
+            .unwrap()
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(
+            vec![
+                String::from("remove_nics.0: More than one nic matches"),
+                String::from("update_nics.0: No nic matches"),
+            ],
+            errors
+        );
+    }
+    #[test]
+    fn max_swap() {
+        let conf = conf();
+        assert_eq!(None, conf.max_swap);
+        let mut update = JailUpdate::empty();
+        update.max_swap = Some(4096);
+        assert_eq!(Some(4096), update.apply(conf).max_swap);
+    }
+    #[test]
+    fn remove_limits() {
+        let mut conf = conf();
+        conf.max_swap = Some(4096);
+        conf.max_open_files = Some(1024);
+        let json = r#"{"max_swap": null, "max_open_files": 0}"#;
+        let update = JailUpdate::from_reader(&config(), json.as_bytes()).unwrap();
+        let conf = update.apply(conf);
+        assert_eq!(None, conf.max_swap);
+        assert_eq!(None, conf.max_open_files);
+    }
+    #[test]
+    fn quota() {
+        let conf = conf();
+        let mut update = JailUpdate::empty();
+        update.quota = Some(20);
+        assert_eq!(20, update.apply(conf).quota);
+    }
+    #[test]
+    fn cpuset() {
+        let conf = conf();
+        let mut update = JailUpdate::empty();
+        update.cpuset = Some(String::from("0-3"));
+        let conf = update.apply(conf);
+        assert_eq!(Some(String::from("0-3")), conf.cpuset);
+        update.cpuset = Some(String::new());
+        assert_eq!(None, update.apply(conf.clone()).cpuset);
+        let json = r#"{"cpuset": [0, 2]}"#;
+        let update = JailUpdate::from_reader(&config(), json.as_bytes()).unwrap();
+        assert_eq!(Some(String::from("0,2")), update.apply(conf).cpuset);
+    }
 
+    #[test]
+    fn exec_commands() {
+        let conf = conf();
+        let update = JailUpdate::from_args(
+            &config(),
+            vec![
+                String::from("init_name=/usr/local/bin/app -f"),
+                String::from("exec_env={\"APP_ENV\": \"prod\"}"),
+            ],
+        ).unwrap();
+        assert!(update.errors(&config()).is_none());
+        let conf = update.apply(conf);
+        assert_eq!(Some(String::from("/usr/local/bin/app -f")), conf.init_name);
+        assert_eq!(Some(&String::from("prod")), conf.exec_env.get("APP_ENV"));
+        let mut update = JailUpdate::empty();
+        update.init_name = Some(String::new());
+        assert_eq!("export APP_ENV='prod'; sh /etc/rc", update.apply(conf).exec_start());
+    }
 }
-
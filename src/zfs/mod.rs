@@ -127,6 +127,23 @@ pub fn snapshot(dataset: &str, snapshot: &str) -> Result<String, Box<Error>> {
     }
 }
 
+/// sets a property on a zfs dataset
+pub fn set(dataset: &str, property: &str, value: &str) -> Result<i32, Box<Error>> {
+    let mut prop = String::from(property);
+    prop.push('=');
+    prop.push_str(value);
+    debug!("Setting ZFS property"; "dataset" => dataset, "property" => prop.clone());
+    let output = Command::new("zfs")
+        .args(&["set", prop.as_str(), dataset])
+        .output()
+        .expect("zfs set failed");
+    if output.status.success() {
+        Ok(0)
+    } else {
+        Err(GenericError::bx("Failed to set property"))
+    }
+}
+
 /// clones a zfs snapshot
 pub fn clone(snapshot: &str, dataset: &str) -> Result<i32, Box<Error>> {
     debug!("Cloning ZFS snapshot"; "snapshot" => snapshot, "dataset" => dataset);